@@ -63,40 +63,125 @@ pub struct Text {
 }
 
 impl Text {
-    fn new(text: String) -> Option<Text> {
-        if text.trim().is_empty() { return None; }
-        return Some(Text { text });
+    fn new(text: String, preserve: bool) -> Option<Text> {
+        if text.is_empty() || (!preserve && text.trim().is_empty()) { return None; }
+        return Some(Text { text: decode_entities(&text) });
     }
 }
 
+fn named_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        "copy" => Some('©'),
+        "reg" => Some('®'),
+        "mdash" => Some('—'),
+        "ndash" => Some('–'),
+        "hellip" => Some('…'),
+        _ => None,
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+pub fn decode_entities(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let name = &rest[1..end];
+            let c = if let Some(hex) = name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+            } else if let Some(decimal) = name.strip_prefix('#') {
+                decimal.parse::<u32>().ok().and_then(char::from_u32)
+            } else {
+                named_entity(name)
+            };
+            return c.map(|c| (c, end + 1));
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    return out;
+}
+
 fn get_attributes(text: String) -> (String, HashMap<String, String>) {
-    
-    let mut parts = text.split(char::is_whitespace);
-    let tag = parts.next().unwrap().to_lowercase();
-    
+
+    let mut chars = text.trim().chars().peekable();
+    let mut tag = String::new();
+    while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+        tag.push(c);
+    }
+    let tag = tag.trim_end_matches('/').to_lowercase();
+
     let mut attributes = HashMap::new();
-    while let Some(attr_pair) = parts.next() {
-        if attr_pair.contains("=") {
-            
-            let mut attr_split = attr_pair.splitn(1, "=");
-            let key = attr_split.next().unwrap().to_string();
-            let mut value = attr_split.next().unwrap_or("").to_string();
-            
-            if value.len() > 2 &&
-                (value.starts_with("'") || value.starts_with("\"")) {
-                    value = value[1..value.len()-1].to_owned();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            key.push(c);
+        }
+        if key.is_empty() || key == "/" {
+            // stray '=' or the slash of a self-closing tag
+            if chars.next().is_none() { break; }
+            continue;
+        }
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            match chars.peek() {
+                Some('"') | Some('\'') => {
+                    let quote = chars.next().unwrap();
+                    for c in chars.by_ref() {
+                        if c == quote { break; }
+                        value.push(c);
+                    }
+                }
+                _ => {
+                    while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                        value.push(c);
+                    }
+                }
             }
-            
-            attributes.insert(key.to_lowercase(), value);
-        
-        } else {
-            attributes.insert(attr_pair.to_lowercase(), String::new());
         }
+
+        attributes.insert(key.to_lowercase(), decode_entities(&value));
     }
 
     return (tag, attributes);
 }
 
+// whitespace is significant anywhere inside a <pre>
+fn in_pre(parent_stack: &[Rc<RefCell<DOMNode>>]) -> bool {
+    return parent_stack.iter().any(|node| match &node.borrow().data {
+        Data::Element(elem) => elem.tag == "pre",
+        Data::Text(_) => false,
+    });
+}
+
 pub fn parse(body: &String) -> Rc<RefCell<DOMNode>> {
 
     let root = DOMNode::new(Data::Element(Element::new(String::from("bowser")).unwrap()));
@@ -108,7 +193,7 @@ pub fn parse(body: &String) -> Rc<RefCell<DOMNode>> {
     let mut inner_text = String::new();
     for c in body.chars() {
         if c == '<' {
-            if let Some(text) = Text::new(inner_text) {
+            if let Some(text) = Text::new(inner_text, in_pre(&parent_stack)) {
                 let node = DOMNode::new(Data::Text(text));
                 parent_stack.last().unwrap().borrow_mut().add_child(&Rc::new(RefCell::new(node)));
             }
@@ -153,7 +238,7 @@ pub fn parse(body: &String) -> Rc<RefCell<DOMNode>> {
         panic!("Invalid HTML: EOF in tag!")
     }
 
-    if let Some(text) = Text::new(inner_text) {
+    if let Some(text) = Text::new(inner_text, in_pre(&parent_stack)) {
         let node = DOMNode::new(Data::Text(text));
         parent_stack.last().unwrap().borrow_mut().add_child(&Rc::new(RefCell::new(node)));
     }
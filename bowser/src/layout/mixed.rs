@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::html::{DOMNode, Data};
+use crate::request::{Stores, Url};
+
+// Elements that load a subresource, and the attribute naming it. Passive
+// content can only change how part of the page looks; active content can
+// rewrite the whole page, so it is never loaded insecurely.
+const PASSIVE_CONTENT: [(&str, &str); 4] = [("img", "src"), ("audio", "src"), ("video", "src"), ("source", "src")];
+const ACTIVE_CONTENT: [(&str, &str); 5] =
+    [("script", "src"), ("link", "href"), ("iframe", "src"), ("object", "data"), ("embed", "src")];
+
+// Subresources of an https page that would come over plain http. Passive
+// ones are shown but flagged, active ones blocked.
+#[derive(Debug, Clone, Default)]
+pub struct MixedContent {
+    pub flagged: Vec<String>,
+    pub blocked: Vec<String>,
+}
+
+impl MixedContent {
+    // The warning to show above the page, if any
+    pub fn warning(&self) -> Option<String> {
+        if self.flagged.is_empty() && self.blocked.is_empty() {
+            return None;
+        }
+        return Some(format!(
+            "Not fully secure: {} insecure item(s) shown, {} blocked",
+            self.flagged.len(),
+            self.blocked.len()
+        ));
+    }
+}
+
+// Whether a subresource fetched for `page` would undo its https, after any
+// upgrade the HSTS store makes
+fn insecure(stores: Stores, page: &Url, resource: &Url) -> bool {
+    let upgraded = stores.hsts.is_some_and(|hsts| hsts.upgrade(resource).is_some());
+    return page.scheme == "https" && resource.scheme == "http" && !upgraded;
+}
+
+// Finds the subresources of a page that count as mixed content
+pub fn mixed_content(node: &Rc<RefCell<DOMNode>>, base: &Url, stores: Stores) -> MixedContent {
+    let mut mixed = MixedContent::default();
+    find_mixed(node, base, stores, &mut mixed);
+    return mixed;
+}
+
+fn find_mixed(node: &Rc<RefCell<DOMNode>>, base: &Url, stores: Stores, mixed: &mut MixedContent) {
+    if let Data::Element(elem) = &node.borrow().data {
+        let resource = |content: &[(&str, &str)]| {
+            let (_, attribute) = content.iter().find(|(tag, _)| *tag == elem.tag)?;
+            return base.join(elem.attributes.get(*attribute)?).ok().filter(|url| insecure(stores, base, url));
+        };
+        // only links that fetch something, such as stylesheets and icons
+        let fetched = elem.tag != "link"
+            || elem.attributes.get("rel").is_some_and(|rel| {
+                return rel
+                    .split_whitespace()
+                    .any(|rel| matches!(rel.to_lowercase().as_str(), "stylesheet" | "icon" | "preload"));
+            });
+        if let Some(url) = resource(&PASSIVE_CONTENT) {
+            mixed.flagged.push(url.to_string());
+        } else if let Some(url) = resource(&ACTIVE_CONTENT).filter(|_| fetched) {
+            mixed.blocked.push(url.to_string());
+        }
+    }
+    for child in &*node.borrow().children.borrow() {
+        find_mixed(child, base, stores, mixed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html::parse;
+    use crate::request::{HeaderMap, Hsts};
+
+    use super::*;
+
+    const PAGE: &str = "<html><body>\
+        <img src=\"http://cdn.example.com/a.png\"><img src=\"https://cdn.example.com/b.png\">\
+        <video src=\"/clip.mp4\"></video><audio src=\"http://media.example.com/song.mp3\"></audio>\
+        <script src=\"http://cdn.example.com/app.js\"></script>\
+        <link rel=\"stylesheet\" href=\"http://cdn.example.com/style.css\">\
+        <link rel=\"canonical\" href=\"http://example.com/\">\
+        <iframe src=\"http://ads.example.com/\"></iframe>\
+        </body></html>";
+
+    #[test]
+    fn passive_and_active() {
+        let dom = parse(&PAGE.to_string());
+        let mixed = mixed_content(&dom, &Url::parse("https://example.com/").unwrap(), Stores::default());
+        assert_eq!(mixed.flagged, ["http://cdn.example.com/a.png", "http://media.example.com/song.mp3"]);
+        // a canonical link fetches nothing, so it is left alone
+        assert_eq!(
+            mixed.blocked,
+            ["http://cdn.example.com/app.js", "http://cdn.example.com/style.css", "http://ads.example.com/"]
+        );
+        assert!(mixed.warning().is_some_and(|warning| warning.contains("2 insecure item(s) shown, 3 blocked")));
+
+        // a plain http page has nothing to lose
+        let mixed = mixed_content(&dom, &Url::parse("http://example.com/").unwrap(), Stores::default());
+        assert!(mixed.flagged.is_empty() && mixed.blocked.is_empty() && mixed.warning().is_none());
+    }
+
+    #[test]
+    fn upgraded_by_hsts() {
+        let hsts = Hsts::new(None);
+        let mut headers = HeaderMap::new();
+        headers.append("Strict-Transport-Security", "max-age=600; includeSubDomains");
+        hsts.store_response(&Url::parse("https://example.com/").unwrap(), &headers);
+        let stores = Stores { hsts: Some(&hsts), ..Stores::default() };
+
+        let dom = parse(&PAGE.to_string());
+        let mixed = mixed_content(&dom, &Url::parse("https://example.com/").unwrap(), stores);
+        assert!(mixed.flagged.is_empty() && mixed.blocked.is_empty());
+    }
+}
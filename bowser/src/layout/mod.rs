@@ -1,14 +1,52 @@
-use druid::widget::{Flex, Label};
-use druid::{Data as DruidData, FontDescriptor, FontFamily, FontStyle, FontWeight, Lens, Widget};
+use druid::piet::InterpolationMode;
+use druid::widget::{Button, Checkbox, FillStrat, Flex, Image, Label, Radio, RadioGroup, TextBox};
+use druid::{
+    Color, Data as DruidData, FontDescriptor, FontFamily, FontStyle, FontWeight, ImageBuf, Lens,
+    LensExt, Selector, Widget, WidgetExt,
+};
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::html::{DOMNode, Data};
+use crate::html::{DOMNode, Data, Element};
+use crate::request::form::{submission, FormValue};
+use crate::request::{default_transport, Cancel, Initiator, Request, Stores, Url};
+
+mod mixed;
+
+pub use mixed::{mixed_content, MixedContent};
+
+// Sent to open a url, as typed into the address bar
+pub const OPEN_URL: Selector<String> = Selector::new("bowser.open-url");
+
+// Sent when a link with a download attribute is clicked, carrying the
+// request for it and the file name the attribute suggests, which may be empty
+pub const DOWNLOAD_URL: Selector<(Request, String)> = Selector::new("bowser.download-url");
+
+// Sent when a link is clicked or a form is submitted, carrying the request
+// it makes
+pub const OPEN_REQUEST: Selector<Request> = Selector::new("bowser.open-request");
+
+const LINK_COLOR: u32 = 0x0000eeff;
+
+// Schemes an <img> may load from. Fetching an about: url would run the
+// action in its query, so only the web and data: urls are allowed.
+const IMAGE_SCHEMES: [&str; 3] = ["http", "https", "data"];
 
 #[derive(Clone, DruidData, Lens)]
 pub struct AppState {
     pub url: String,
+    // true while any page load is in flight
+    pub loading: bool,
+    // values the user has entered into form controls, by control id
+    pub fields: Arc<HashMap<usize, String>>,
+    // what is typed into the login dialog
+    pub username: String,
+    pub password: String,
 }
 
 #[derive(Debug)]
@@ -32,11 +70,13 @@ impl LayoutNode {
     // }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Style {
     size: f64,
     bold: bool,
     italic: bool,
+    monospace: bool,
+    color: u32,
 }
 
 impl Style {
@@ -45,6 +85,8 @@ impl Style {
             size: 16.0,
             bold: false,
             italic: false,
+            monospace: false,
+            color: 0x000000ff,
         };
     }
 }
@@ -90,7 +132,11 @@ const _BLOCK_ELEMENTS: [&str; 37] = [
 ];
 
 fn get_font(style: &Style) -> FontDescriptor {
-    return FontDescriptor::new(FontFamily::SERIF)
+    let family = match style.monospace {
+        true => FontFamily::MONOSPACE,
+        false => FontFamily::SERIF,
+    };
+    return FontDescriptor::new(family)
         .with_size(style.size)
         .with_weight(match style.bold {
             true => FontWeight::BOLD,
@@ -102,8 +148,31 @@ fn get_font(style: &Style) -> FontDescriptor {
         });
 }
 
-fn open_tag(tag: &String, style: &Style) -> Style {
-    match tag.as_str() {
+// "#rrggbb", "#rgb" or a handful of basic color names, as rgba
+fn parse_color(color: &str) -> Option<u32> {
+    let color = color.trim().to_lowercase();
+    if let Some(hex) = color.strip_prefix('#') {
+        let hex = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect(),
+            6 => hex.to_string(),
+            _ => return None,
+        };
+        return u32::from_str_radix(&hex, 16).ok().map(|rgb| rgb << 8 | 0xff);
+    }
+    match color.as_str() {
+        "black" => Some(0x000000ff),
+        "white" => Some(0xffffffff),
+        "red" => Some(0xff0000ff),
+        "green" => Some(0x008000ff),
+        "blue" => Some(0x0000ffff),
+        "gray" | "grey" => Some(0x808080ff),
+        "purple" => Some(0x800080ff),
+        _ => None,
+    }
+}
+
+fn open_tag(elem: &Element, style: &Style) -> Style {
+    match elem.tag.as_str() {
         "b" => {
             return Style {
                 bold: true,
@@ -128,6 +197,37 @@ fn open_tag(tag: &String, style: &Style) -> Style {
                 ..(*style)
             }
         }
+        "a" => {
+            return Style {
+                color: LINK_COLOR,
+                ..(*style)
+            }
+        }
+        "h1" | "h2" | "h3" => {
+            let grow = match elem.tag.as_str() {
+                "h1" => 12.0,
+                "h2" => 8.0,
+                _ => 4.0,
+            };
+            return Style {
+                size: style.size + grow,
+                bold: true,
+                ..(*style)
+            }
+        }
+        "pre" | "code" | "tt" => {
+            return Style {
+                monospace: true,
+                ..(*style)
+            }
+        }
+        "font" => {
+            let color = elem.attributes.get("color").and_then(|color| parse_color(color));
+            return Style {
+                color: color.unwrap_or(style.color),
+                ..(*style)
+            }
+        }
         _ => {
             return Style { ..(*style) };
         }
@@ -136,24 +236,501 @@ fn open_tag(tag: &String, style: &Style) -> Style {
 
 fn label(text: &String, style: &Style) -> impl Widget<AppState> {
     let font = get_font(style);
-    let mut label = Label::new(text.as_str()).with_font(font);
+    let mut label = Label::new(text.as_str())
+        .with_font(font)
+        .with_text_color(Color::from_rgba32_u32(style.color));
     label.set_line_break_mode(druid::widget::LineBreaking::WordWrap);
     return label;
 }
 
-pub fn recurse(node: &Rc<RefCell<DOMNode>>, style: &Style) -> Vec<impl Widget<AppState>> {
+#[derive(Clone)]
+struct Link {
+    request: Request,
+    // the download attribute, if the link has one
+    download: Option<String>,
+}
+
+fn internal(url: &Url) -> bool {
+    return url.scheme == "about" || (url.scheme == "view-source" && url.without_scheme().starts_with("about:"));
+}
+
+// Whether a page at `base` may navigate to `url`. Internal pages can only
+// be reached from other internal pages or the address bar, so a web page
+// cannot open them, or their actions, on the user's behalf.
+fn may_navigate(base: &Url, url: &Url) -> bool {
+    return !internal(url) || internal(base);
+}
+
+// Who a navigation from the page at `base` is made for. Internal pages such
+// as history and bookmarks act for the user rather than for a site.
+fn navigation(base: &Url) -> Initiator {
+    if internal(base) {
+        return Initiator::User;
+    }
+    return Initiator::Navigation(base.clone());
+}
+
+fn link_target(elem: &Element, base: &Url) -> Option<Link> {
+    if elem.tag != "a" {
+        return None;
+    }
+    return elem
+        .attributes
+        .get("href")
+        .and_then(|href| base.join(href).ok())
+        .filter(|url| may_navigate(base, url))
+        .map(|url| Link {
+            request: Request::get(&url).initiator(navigation(base)),
+            download: elem.attributes.get("download").cloned(),
+        });
+}
+
+fn clickable(widget: Box<dyn Widget<AppState>>, target: &Option<Link>) -> Box<dyn Widget<AppState>> {
+    match target {
+        Some(link) => {
+            let link = link.clone();
+            return Box::new(widget.on_click(move |ctx, _data, _env| {
+                match &link.download {
+                    Some(name) => ctx.submit_command(DOWNLOAD_URL.with((link.request.clone(), name.clone()))),
+                    None => ctx.submit_command(OPEN_REQUEST.with(link.request.clone())),
+                }
+            }));
+        }
+        None => { return widget; }
+    }
+}
+
+struct Run {
+    text: String,
+    style: Style,
+    target: Option<Link>,
+}
+
+fn collect_runs(node: &Rc<RefCell<DOMNode>>, style: &Style, target: &Option<Link>, base: &Url, runs: &mut Vec<Run>) {
+    match &node.borrow().data {
+        Data::Text(text) => runs.push(Run {
+            text: text.text.replace('\t', "    "),
+            style: style.clone(),
+            target: target.clone(),
+        }),
+        Data::Element(elem) => {
+            let style = open_tag(elem, style);
+            let target = link_target(elem, base).or(target.clone());
+            for child in &*node.borrow().children.borrow() {
+                collect_runs(child, &style, &target, base, runs);
+            }
+        }
+    }
+}
+
+// Lays out a <pre> line by line, keeping every run of styled text on its
+// line and never wrapping
+fn preformatted(node: &Rc<RefCell<DOMNode>>, style: &Style, base: &Url) -> Vec<Box<dyn Widget<AppState>>> {
+    let mut runs = Vec::new();
+    collect_runs(node, style, &None, base, &mut runs);
+    if let Some(run) = runs.first_mut() {
+        if run.text.starts_with('\n') {
+            run.text.remove(0);
+        }
+    }
+
+    let mut lines: Vec<Box<dyn Widget<AppState>>> = Vec::new();
+    let mut row = Flex::row();
+    let mut row_empty = true;
+    for run in runs {
+        for (i, part) in run.text.split('\n').enumerate() {
+            if i > 0 {
+                if row_empty {
+                    row.add_child(pre_label(&" ".to_string(), &run.style));
+                }
+                lines.push(Box::new(row));
+                row = Flex::row();
+                row_empty = true;
+            }
+            if !part.is_empty() {
+                let label = Box::new(pre_label(&part.to_string(), &run.style));
+                row.add_child(clickable(label, &run.target));
+                row_empty = false;
+            }
+        }
+    }
+    if !row_empty {
+        lines.push(Box::new(row));
+    }
+    return lines;
+}
+
+fn pre_label(text: &String, style: &Style) -> impl Widget<AppState> {
+    let mut label = Label::new(text.as_str())
+        .with_font(get_font(style))
+        .with_text_color(Color::from_rgba32_u32(style.color));
+    label.set_line_break_mode(druid::widget::LineBreaking::Overflow);
+    return label;
+}
+
+// Decoded images for a page by the url they were fetched from, gathered
+// before layout so that no image is fetched on the UI thread
+pub type Images = HashMap<String, ImageBuf>;
+
+fn image_sources(node: &Rc<RefCell<DOMNode>>, base: &Url, sources: &mut Vec<Url>) {
+    if let Data::Element(elem) = &node.borrow().data {
+        if elem.tag == "img" {
+            let url = elem.attributes.get("src").and_then(|src| base.join(src).ok());
+            if let Some(url) = url.filter(|url| IMAGE_SCHEMES.contains(&url.scheme.as_str())) {
+                if !sources.contains(&url) {
+                    sources.push(url);
+                }
+            }
+        }
+    }
+    for child in &*node.borrow().children.borrow() {
+        image_sources(child, base, sources);
+    }
+}
+
+// Fetches every image in the document, skipping any that fail. Stops early
+// once `cancel` is cancelled.
+pub fn fetch_images(node: &Rc<RefCell<DOMNode>>, base: &Url, cancel: &Cancel) -> Images {
+    let mut sources = Vec::new();
+    image_sources(node, base, &mut sources);
+    let stores = Stores { cancel: Some(cancel), ..Stores::shared() };
+    let mut images = Images::new();
+    for url in sources {
+        let image = Request::get(&url)
+            .initiator(Initiator::Subresource(base.clone()))
+            .send_with(&mut default_transport(), stores)
+            .ok()
+            .filter(|response| response.status == 200)
+            .and_then(|response| ImageBuf::from_data(&response.body).ok());
+        if let Some(image) = image {
+            images.insert(url.to_string(), image);
+        }
+        if cancel.is_cancelled() {
+            break;
+        }
+    }
+    return images;
+}
+
+fn image(elem: &Element, base: &Url, images: &Images) -> Box<dyn Widget<AppState>> {
+    let alt = elem.attributes.get("alt").cloned().unwrap_or_default();
+    let img_data = elem
+        .attributes
+        .get("src")
+        .and_then(|src| base.join(src).ok())
+        .and_then(|url| images.get(&url.to_string()));
+    match img_data {
+        Some(img_data) => {
+            return Box::new(
+                Image::new(img_data.clone())
+                    .fill_mode(FillStrat::None)
+                    .interpolation_mode(InterpolationMode::Bilinear),
+            );
+        }
+        None => {
+            return Box::new(label(&format!("[{}]", alt), &Style::new()));
+        }
+    }
+}
+
+fn next_id() -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    return NEXT_ID.fetch_add(1, Ordering::Relaxed);
+}
+
+// A lens onto the value of one form control. Until the user edits it the
+// control has the value the page gave it.
+#[derive(Clone)]
+struct Field {
+    id: usize,
+    default: Rc<RefCell<String>>,
+}
+
+impl Field {
+    fn new(default: &str) -> Field {
+        return Field { id: next_id(), default: Rc::new(RefCell::new(default.to_string())) };
+    }
+
+    fn value(&self, data: &AppState) -> String {
+        return data.fields.get(&self.id).cloned().unwrap_or_else(|| self.default.borrow().clone());
+    }
+}
+
+impl Lens<AppState, String> for Field {
+    fn with<V, F: FnOnce(&String) -> V>(&self, data: &AppState, f: F) -> V {
+        return f(&self.value(data));
+    }
+
+    fn with_mut<V, F: FnOnce(&mut String) -> V>(&self, data: &mut AppState, f: F) -> V {
+        let mut value = self.value(data);
+        let result = f(&mut value);
+        if value != self.value(data) {
+            Arc::make_mut(&mut data.fields).insert(self.id, value);
+        }
+        return result;
+    }
+}
+
+enum Control {
+    Text { name: String, field: Field },
+    Checkbox { name: String, value: String, field: Field },
+    Radio { name: String, field: Field },
+    File { name: String, field: Field },
+    Submit { name: String, value: String, id: usize },
+}
+
+struct Form {
+    action: Url,
+    // where the form is, which decides whether it may submit to `action`
+    base: Url,
+    method: String,
+    enctype: String,
+    controls: RefCell<Vec<Control>>,
+    // radio buttons with the same name share one field
+    radios: RefCell<HashMap<String, Field>>,
+}
+
+impl Form {
+    fn new(elem: &Element, base: &Url) -> Form {
+        let attribute = |name: &str| elem.attributes.get(name).cloned().unwrap_or_default();
+        let action = elem
+            .attributes
+            .get("action")
+            .filter(|action| !action.trim().is_empty())
+            .and_then(|action| base.join(action).ok())
+            .unwrap_or_else(|| base.clone());
+        return Form {
+            action,
+            base: base.clone(),
+            method: attribute("method"),
+            enctype: attribute("enctype"),
+            controls: RefCell::new(Vec::new()),
+            radios: RefCell::new(HashMap::new()),
+        };
+    }
+
+    fn add(&self, control: Control) {
+        self.controls.borrow_mut().push(control);
+    }
+
+    // The form data set in tree order, as submitted by button `submitter`
+    fn entries(&self, data: &AppState, submitter: usize) -> Vec<(String, FormValue)> {
+        let mut entries = Vec::new();
+        for control in self.controls.borrow().iter() {
+            match control {
+                Control::Text { name, field } => {
+                    entries.push((name.clone(), FormValue::Text(field.value(data))));
+                }
+                Control::Checkbox { name, value, field } if field.value(data) == "on" => {
+                    entries.push((name.clone(), FormValue::Text(value.clone())));
+                }
+                Control::Radio { name, field } if !field.value(data).is_empty() => {
+                    entries.push((name.clone(), FormValue::Text(field.value(data))));
+                }
+                Control::File { name, field } => {
+                    let path = field.value(data);
+                    let path = if path.trim().is_empty() { None } else { Some(PathBuf::from(path.trim())) };
+                    entries.push((name.clone(), FormValue::File(path)));
+                }
+                Control::Submit { name, value, id } if *id == submitter && !name.is_empty() => {
+                    entries.push((name.clone(), FormValue::Text(value.clone())));
+                }
+                _ => {}
+            }
+        }
+        return entries;
+    }
+}
+
+fn text_content(node: &Rc<RefCell<DOMNode>>) -> String {
+    match &node.borrow().data {
+        Data::Text(text) => { return text.text.clone(); }
+        Data::Element(_) => {
+            return node.borrow().children.borrow().iter().map(text_content).collect();
+        }
+    }
+}
+
+fn submit_button(text: &str, name: &str, value: &str, form: &Rc<Form>) -> Box<dyn Widget<AppState>> {
+    let id = next_id();
+    form.add(Control::Submit { name: name.to_string(), value: value.to_string(), id });
+    let form = Rc::clone(form);
+    return Box::new(Button::new(text.to_string()).on_click(move |ctx, data: &mut AppState, _env| {
+        if !may_navigate(&form.base, &form.action) {
+            println!("ERROR: {} may not submit to {}", form.base, form.action);
+            return;
+        }
+        let entries = form.entries(data, id);
+        match submission(&form.action, &form.method, &form.enctype, &entries) {
+            Ok(request) => ctx.submit_command(OPEN_REQUEST.with(request.initiator(navigation(&form.base)))),
+            Err(error) => println!("ERROR: could not submit form: {}", error),
+        }
+    }));
+}
+
+// The widget for a form control, registering it with its form. Controls
+// outside a form are still drawn but never submitted.
+fn control(node: &Rc<RefCell<DOMNode>>, elem: &Element, form: &Option<Rc<Form>>) -> Option<Box<dyn Widget<AppState>>> {
+    let attribute = |name: &str| elem.attributes.get(name).cloned().unwrap_or_default();
+    let name = attribute("name");
+    let value = attribute("value");
+    let disabled = elem.attributes.contains_key("disabled");
+    let register = |control: Control| {
+        if let (Some(form), false, false) = (form, disabled, name.is_empty()) {
+            form.add(control);
+        }
+    };
+
+    match elem.tag.as_str() {
+        "textarea" => {
+            // a newline right after <textarea> is not part of its value
+            let text = text_content(node);
+            let field = Field::new(text.strip_prefix('\n').unwrap_or(&text));
+            register(Control::Text { name: name.clone(), field: field.clone() });
+            return Some(Box::new(TextBox::multiline().lens(field).fix_width(300.0)));
+        }
+        "select" => {
+            let options: Vec<(String, String)> = node
+                .borrow()
+                .children
+                .borrow()
+                .iter()
+                .filter_map(|child| match &child.borrow().data {
+                    Data::Element(option) if option.tag == "option" => {
+                        let text = text_content(child).trim().to_string();
+                        let value = option.attributes.get("value").cloned().unwrap_or_else(|| text.clone());
+                        Some((text, value))
+                    }
+                    _ => None,
+                })
+                .collect();
+            let selected = node.borrow().children.borrow().iter().find_map(|child| match &child.borrow().data {
+                Data::Element(option) if option.tag == "option" && option.attributes.contains_key("selected") => {
+                    Some(option.attributes.get("value").cloned().unwrap_or_else(|| text_content(child).trim().to_string()))
+                }
+                _ => None,
+            });
+            let default = selected.or_else(|| options.first().map(|(_, value)| value.clone())).unwrap_or_default();
+            let field = Field::new(&default);
+            register(Control::Text { name: name.clone(), field: field.clone() });
+            return Some(Box::new(RadioGroup::column(options).lens(field)));
+        }
+        "button" => {
+            let text = text_content(node).trim().to_string();
+            match form {
+                Some(form) if matches!(attribute("type").to_lowercase().as_str(), "" | "submit") => {
+                    return Some(submit_button(&text, &name, &value, form));
+                }
+                _ => { return Some(Box::new(Button::new(text))); }
+            }
+        }
+        "input" => {}
+        _ => { return None; }
+    }
+
+    let kind = attribute("type").to_lowercase();
+    match kind.as_str() {
+        "hidden" => {
+            register(Control::Text { name: name.clone(), field: Field::new(&value) });
+            return None;
+        }
+        "submit" => {
+            let text = if value.is_empty() { "Submit".to_string() } else { value.clone() };
+            match form {
+                Some(form) => { return Some(submit_button(&text, &name, &value, form)); }
+                None => { return Some(Box::new(Button::new(text))); }
+            }
+        }
+        "reset" | "button" | "image" => {
+            return Some(Box::new(Button::new(if value.is_empty() { kind.clone() } else { value.clone() })));
+        }
+        "checkbox" => {
+            let checked = elem.attributes.contains_key("checked");
+            let field = Field::new(if checked { "on" } else { "" });
+            let value = if value.is_empty() { "on".to_string() } else { value.clone() };
+            register(Control::Checkbox { name: name.clone(), value, field: field.clone() });
+            let checkbox = Checkbox::new("").lens(field.map(
+                |value: &String| value == "on",
+                |value: &mut String, checked: bool| *value = if checked { "on".to_string() } else { String::new() },
+            ));
+            return Some(Box::new(checkbox));
+        }
+        "radio" => {
+            let value = if value.is_empty() { "on".to_string() } else { value.clone() };
+            let existing = form.as_ref().and_then(|form| form.radios.borrow().get(&name).cloned());
+            let field = match existing {
+                Some(field) => field,
+                None => {
+                    let field = Field::new("");
+                    if let Some(form) = form {
+                        form.radios.borrow_mut().insert(name.clone(), field.clone());
+                    }
+                    register(Control::Radio { name: name.clone(), field: field.clone() });
+                    field
+                }
+            };
+            if elem.attributes.contains_key("checked") {
+                *field.default.borrow_mut() = value.clone();
+            }
+            return Some(Box::new(Radio::new("", value).lens(field)));
+        }
+        "file" => {
+            let field = Field::new("");
+            register(Control::File { name: name.clone(), field: field.clone() });
+            return Some(Box::new(TextBox::new().with_placeholder("path to a file").lens(field).fix_width(300.0)));
+        }
+        _ => {
+            let field = Field::new(&value);
+            register(Control::Text { name: name.clone(), field: field.clone() });
+            let placeholder = attribute("placeholder");
+            return Some(Box::new(TextBox::new().with_placeholder(placeholder).lens(field).fix_width(200.0)));
+        }
+    }
+}
+
+pub fn recurse(
+    node: &Rc<RefCell<DOMNode>>,
+    style: &Style,
+    base: &Url,
+    images: &Images,
+) -> Vec<Box<dyn Widget<AppState>>> {
+    return walk(node, style, base, images, &None);
+}
+
+fn walk(
+    node: &Rc<RefCell<DOMNode>>,
+    style: &Style,
+    base: &Url,
+    images: &Images,
+    form: &Option<Rc<Form>>,
+) -> Vec<Box<dyn Widget<AppState>>> {
     match &node.borrow().data {
         Data::Text(text) => {
-            let mut body = Vec::new();
-            body.push(label(&text.text, style));
+            let mut body: Vec<Box<dyn Widget<AppState>>> = Vec::new();
+            body.push(Box::new(label(&text.text, style)));
             return body;
         }
+        Data::Element(elem) if elem.tag == "img" => {
+            return vec![image(elem, base, images)];
+        }
+        Data::Element(elem) if elem.tag == "pre" => {
+            return preformatted(node, style, base);
+        }
+        Data::Element(elem) if matches!(elem.tag.as_str(), "input" | "textarea" | "select" | "button") => {
+            return control(node, elem, form).into_iter().collect();
+        }
         Data::Element(elem) => {
-            let style = open_tag(&elem.tag, style);
-            let mut body = Vec::new();
+            let style = open_tag(elem, style);
+            let target = link_target(elem, base);
+            // nested forms are not allowed, so an inner <form> is ignored
+            let form = match (elem.tag.as_str(), form) {
+                ("form", None) => Some(Rc::new(Form::new(elem, base))),
+                _ => form.clone(),
+            };
+            let mut body: Vec<Box<dyn Widget<AppState>>> = Vec::new();
             for child in &*node.borrow().children.borrow() {
-                for label in recurse(&Rc::clone(child), &style) {
-                    body.push(label);
+                for widget in walk(&Rc::clone(child), &style, base, images, &form) {
+                    body.push(clickable(widget, &target));
                 }
             }
             return body;
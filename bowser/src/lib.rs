@@ -1,4 +1,5 @@
 pub mod css;
 pub mod html;
 pub mod layout;
+pub mod profile;
 pub mod request;
\ No newline at end of file
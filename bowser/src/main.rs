@@ -1,48 +1,385 @@
 use druid::piet::InterpolationMode;
-use druid::widget::{prelude::*, Button, FillStrat, Flex, Image, Label, Scroll, TextBox};
-use druid::{AppLauncher, ImageBuf, Widget, WidgetExt, WindowConfig, WindowDesc, WindowLevel};
+use druid::widget::{
+    prelude::*, Button, Either, FillStrat, Flex, Image, Label, Scroll, Spinner, Svg, SvgData,
+    TextBox,
+};
+use druid::{
+    commands, theme, AppDelegate, AppLauncher, Color, Command, DelegateCtx, ExtEventSink, Handled, ImageBuf,
+    KbKey, Selector, SingleUse, Target, Widget, WidgetExt, WindowDesc, WindowId,
+};
+use std::collections::HashMap;
 use std::rc::Rc;
-use std::{env, str, fs};
-// use std::sync::Arc;
-
-use bowser::html::{parse, print_dom};
-use bowser::layout::{recurse, AppState, Style};
-use bowser::request::request;
-
-fn load(url: &String) -> impl Widget<AppState> {
-    let (headers, body) = request(url);
-    assert!(headers.contains_key("content-type"));
-    let content_type = headers
-        .get("content-type")
-        .unwrap()
-        .splitn(2, ';')
-        .next()
-        .unwrap();
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{env, fs, thread};
+
+use bowser::html::{escape, parse, print_dom};
+use bowser::layout::{
+    fetch_images, mixed_content, recurse, AppState, Images, Style, DOWNLOAD_URL, OPEN_REQUEST, OPEN_URL,
+};
+use bowser::request::download::{self, Divert};
+use bowser::request::{bookmarks, gemini, history};
+use bowser::request::{
+    default_transport, request, Cancel, Credentials, Prompt, Request, RequestError, Response, Stores, Url,
+};
+
+// Sent from a loader thread once a page and its images have been fetched
+const PAGE_LOADED: Selector<SingleUse<Loaded>> = Selector::new("bowser.page-loaded");
+
+// Cancels every page load in flight
+const STOP: Selector = Selector::new("bowser.stop");
+
+// Sent from a loader thread when a server or proxy wants credentials
+const ASK_CREDENTIALS: Selector<SingleUse<Ask>> = Selector::new("bowser.ask-credentials");
+
+// Closes the login dialog, true to log in with what was typed
+const ANSWER_CREDENTIALS: Selector<bool> = Selector::new("bowser.answer-credentials");
+
+// How often a loader thread waiting on its request checks for a stop
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+struct Loaded {
+    id: u64,
+    url: String,
+    result: Result<Response, RequestError>,
+    images: Images,
+}
+
+struct Ask {
+    target: String,
+    realm: String,
+    proxy: bool,
+    reply: Sender<Option<Credentials>>,
+}
+
+// Asks for credentials with a login dialog, blocking the loader thread until
+// it is answered. The lock keeps it to one dialog at a time.
+struct DialogPrompt {
+    sink: Mutex<ExtEventSink>,
+}
+
+impl Prompt for DialogPrompt {
+    fn ask(&self, target: &str, realm: &str, proxy: bool) -> Option<Credentials> {
+        let sink = self.sink.lock().unwrap();
+        let (reply, answer) = mpsc::channel();
+        let ask = Ask { target: target.to_string(), realm: realm.to_string(), proxy, reply };
+        sink.submit_command(ASK_CREDENTIALS, SingleUse::new(ask), Target::Auto).ok()?;
+        return answer.recv().ok().flatten();
+    }
+}
+
+fn error_page(url: &String, error: &RequestError) -> Flex<AppState> {
+    return Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .with_child(Label::new(format!("Failed to load {}", url)).with_text_size(20.0))
+        .with_default_spacer()
+        .with_child(Label::new(error.to_string()));
+}
+
+// Runs on a loader thread. HTML is parsed here only to find the images it
+// needs; the DOM is built from Rc and cannot cross threads, so the page is
+// parsed again for layout on the UI thread.
+fn fetch(page: Request, cancel: &Cancel, prompt: Arc<DialogPrompt>) -> (Result<Response, RequestError>, Images) {
+    // The request gets a thread and a Cancel of its own, so a body diverted
+    // to the download manager keeps arriving after the page load is over,
+    // and only pausing the download stops it
+    let transfer = Cancel::new();
+    let (sender, receiver) = mpsc::channel();
+    let watched = transfer.clone();
+    thread::spawn(move || {
+        let diverted = sender.clone();
+        let divert = Divert::new(download::shared(), &page, &watched).on_divert(move |_| {
+            let _ = diverted.send(None);
+        });
+        let stores = Stores { cancel: Some(&watched), sink: Some(&divert), prompt: Some(&*prompt), ..Stores::shared() };
+        let result = page.send_with(&mut default_transport(), stores);
+        match divert.diverted() {
+            Some(id) => download::shared().settle(id, &result),
+            None => { let _ = sender.send(Some(result)); }
+        }
+    });
+    let result = loop {
+        match receiver.recv_timeout(CANCEL_POLL) {
+            Ok(Some(result)) => break result,
+            // the body is going to the download manager, so show its progress
+            Ok(None) => { return (request(&"about:downloads".to_string()), Images::new()); }
+            Err(RecvTimeoutError::Timeout) => {
+                if cancel.is_cancelled() {
+                    transfer.cancel();
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => { return (Err(RequestError::Cancelled), Images::new()); }
+        }
+    };
+    let response = match result {
+        Ok(response) => response,
+        Err(error) => { return (Err(error), Images::new()); }
+    };
+    history::shared().visit(&response.final_url);
+    let mut images = Images::new();
+    if response.mime_type() == "text/html" {
+        let dom_root = parse(&response.text());
+        images = fetch_images(&dom_root, &response.final_url, cancel);
+    }
+    return (Ok(response), images);
+}
+
+fn load(url: &String, result: Result<Response, RequestError>, images: &Images) -> (String, impl Widget<AppState>) {
+    let response = match result {
+        Ok(response) => response,
+        Err(error) => {
+            println!("ERROR: {}", error);
+            return (url.to_string(), Scroll::new(error_page(url, &error)).vertical());
+        }
+    };
+    let text = response.text();
+    let content_type = response.mime_type();
+    let (base, body) = (response.final_url, response.body);
     let mut col = Flex::column().cross_axis_alignment(druid::widget::CrossAxisAlignment::Start);
-    let content = match content_type {
+    let content = match content_type.as_str() {
         "text/html" => {
-            let body_str = str::from_utf8(&body).expect("Failed to convert [u8] to string");
-            let dom_root = parse(&body_str.to_string());
+            let dom_root = parse(&text);
             print_dom(&Rc::clone(&dom_root), 0);
             // let layout_root = layout(&Rc::clone(&dom_root), &Style::new());
             // render_page(&Rc::clone(&layout_root))
-            let body_widgets = recurse(&Rc::clone(&dom_root), &Style::new());
+            let mixed = mixed_content(&dom_root, &base, Stores::shared());
+            if let Some(warning) = mixed.warning() {
+                col.add_child(Label::new(warning).with_text_color(Color::rgb8(0xc0, 0x40, 0x00)));
+                col.add_default_spacer();
+            }
+            let body_widgets = recurse(&Rc::clone(&dom_root), &Style::new(), &base, images);
             for widget in body_widgets {
                 col.add_child(widget);
             }
             col
         }
-        "image/png" => {
-            let img_data =
-                ImageBuf::from_data(&body).expect("Failed to store bytes in image buffer");
-            let img = Image::new(img_data)
-                .fill_mode(FillStrat::Fill)
-                .interpolation_mode(InterpolationMode::Bilinear);
-            col.with_child(img)
+        "image/svg+xml" => {
+            match text.parse::<SvgData>().ok() {
+                Some(svg_data) => col.with_child(Svg::new(svg_data).fill_mode(FillStrat::None)),
+                None => col.with_child(Label::new("Invalid SVG image")),
+            }
+        }
+        image if image.starts_with("image/") => {
+            match ImageBuf::from_data(&body) {
+                Ok(img_data) => {
+                    let img = Image::new(img_data)
+                        .fill_mode(FillStrat::Fill)
+                        .interpolation_mode(InterpolationMode::Bilinear);
+                    col.with_child(img)
+                }
+                Err(_) => col.with_child(Label::new("Invalid image data")),
+            }
+        }
+        "text/gemini" => {
+            let dom_root = parse(&gemini::to_html(&text));
+            for widget in recurse(&dom_root, &Style::new(), &base, images) {
+                col.add_child(widget);
+            }
+            col
+        }
+        mime if mime.starts_with("text/") => {
+            let dom_root = parse(&format!("<pre>{}</pre>", escape(&text)));
+            for widget in recurse(&dom_root, &Style::new(), &base, &Images::new()) {
+                col.add_child(widget);
+            }
+            col
         }
-        _ => col.with_child(Label::new("Unknown content type")),
+        other => col.with_child(Label::new(format!("Cannot display {}", other))),
     };
-    return Scroll::new(content).vertical();
+    return (base.to_string(), Scroll::new(content).vertical());
+}
+
+// A text field that shows a dot for every character typed into it
+struct PasswordBox {
+    dots: Label<String>,
+}
+
+impl PasswordBox {
+    fn new() -> PasswordBox {
+        return PasswordBox { dots: Label::dynamic(|password: &String, _| "\u{2022}".repeat(password.chars().count())) };
+    }
+}
+
+impl Widget<String> for PasswordBox {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut String, _env: &Env) {
+        match event {
+            Event::MouseDown(_) => ctx.request_focus(),
+            Event::KeyDown(key) if ctx.is_focused() && !(key.mods.ctrl() || key.mods.meta()) => {
+                match &key.key {
+                    KbKey::Character(typed) => data.push_str(typed),
+                    KbKey::Backspace => {
+                        data.pop();
+                    }
+                    _ => { return; }
+                }
+                ctx.set_handled();
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &String, env: &Env) {
+        match event {
+            LifeCycle::BuildFocusChain => ctx.register_for_focus(),
+            LifeCycle::FocusChanged(_) => ctx.request_paint(),
+            _ => {}
+        }
+        self.dots.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &String, data: &String, env: &Env) {
+        self.dots.update(ctx, old_data, data, env);
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &String, env: &Env) -> Size {
+        return self.dots.layout(ctx, bc, data, env);
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &String, env: &Env) {
+        let border = if ctx.is_focused() { env.get(theme::PRIMARY_LIGHT) } else { env.get(theme::BORDER_DARK) };
+        let bounds = ctx.size().to_rect().inset(-0.5);
+        ctx.stroke(bounds, &border, 1.0);
+        self.dots.paint(ctx, data, env);
+    }
+}
+
+fn login_dialog(ask: &Ask) -> impl Widget<AppState> {
+    let asking = if ask.proxy { format!("The proxy {}", ask.target) } else { ask.target.clone() };
+    return Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .with_child(Label::new(format!("{} wants a user name and password", asking)))
+        .with_child(Label::new(format!("Realm: {}", ask.realm)))
+        .with_default_spacer()
+        .with_child(TextBox::new().with_placeholder("User name").lens(AppState::username).fix_width(250.0))
+        .with_child(Label::new("Password"))
+        .with_child(PasswordBox::new().lens(AppState::password).fix_width(250.0))
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(Button::new("Log in").on_click(|ctx, _state: &mut AppState, _env| {
+                    ctx.submit_command(ANSWER_CREDENTIALS.with(true));
+                }))
+                .with_child(Button::new("Cancel").on_click(|ctx, _state: &mut AppState, _env| {
+                    ctx.submit_command(ANSWER_CREDENTIALS.with(false));
+                })),
+        )
+        .padding(10.0);
+}
+
+struct Delegate {
+    sink: ExtEventSink,
+    // loads in flight by id, so they can be stopped
+    loads: HashMap<u64, Cancel>,
+    next_id: u64,
+    prompt: Arc<DialogPrompt>,
+    // the open login dialog and where its answer goes
+    login: Option<(WindowId, Sender<Option<Credentials>>)>,
+}
+
+impl Delegate {
+    fn start(&mut self, request: Request, data: &mut AppState) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let cancel = Cancel::new();
+        self.loads.insert(id, cancel.clone());
+        data.loading = true;
+
+        let sink = self.sink.clone();
+        let prompt = Arc::clone(&self.prompt);
+        thread::spawn(move || {
+            let url = request.url.without_userinfo().to_string();
+            let (result, images) = fetch(request, &cancel, prompt);
+            let loaded = Loaded { id, url, result, images };
+            if sink.submit_command(PAGE_LOADED, SingleUse::new(loaded), Target::Auto).is_err() {
+                println!("ERROR: page loaded after the app closed");
+            }
+        });
+    }
+}
+
+fn open_window(ctx: &mut DelegateCtx, data: &mut AppState, url: &String, result: Result<Response, RequestError>, images: &Images) {
+    let (final_url, page) = load(url, result, images);
+    data.url = final_url.clone();
+    ctx.new_window(
+        WindowDesc::new(page)
+            .title(final_url)
+            .window_size(Size::new(500., 500.)),
+    );
+}
+
+impl AppDelegate<AppState> for Delegate {
+    fn command(
+        &mut self,
+        ctx: &mut DelegateCtx,
+        _target: Target,
+        cmd: &Command,
+        data: &mut AppState,
+        _env: &Env,
+    ) -> Handled {
+        if let Some(url) = cmd.get(OPEN_URL) {
+            match Url::parse(url) {
+                Ok(parsed) => self.start(Request::get(&parsed), data),
+                Err(error) => open_window(ctx, data, url, Err(error.into()), &Images::new()),
+            }
+            return Handled::Yes;
+        }
+        if let Some((request, name)) = cmd.get(DOWNLOAD_URL) {
+            let name = Some(name.clone()).filter(|name| !name.is_empty());
+            download::shared().start(request.clone(), name);
+            ctx.submit_command(OPEN_URL.with("about:downloads".to_string()));
+            return Handled::Yes;
+        }
+        if let Some(request) = cmd.get(OPEN_REQUEST) {
+            self.start(request.clone(), data);
+            return Handled::Yes;
+        }
+        if cmd.is(STOP) {
+            for (_, cancel) in self.loads.drain() {
+                cancel.cancel();
+            }
+            data.loading = false;
+            return Handled::Yes;
+        }
+        if let Some(loaded) = cmd.get(PAGE_LOADED).and_then(SingleUse::take) {
+            // a stopped load was already forgotten, so its result is dropped
+            let stopped = self.loads.remove(&loaded.id).is_none();
+            data.loading = !self.loads.is_empty();
+            if !stopped {
+                open_window(ctx, data, &loaded.url, loaded.result, &loaded.images);
+            }
+            return Handled::Yes;
+        }
+        if let Some(ask) = cmd.get(ASK_CREDENTIALS).and_then(SingleUse::take) {
+            data.username.clear();
+            data.password.clear();
+            let window = WindowDesc::new(login_dialog(&ask))
+                .title("Log in")
+                .window_size(Size::new(360., 200.));
+            self.login = Some((window.id, ask.reply));
+            ctx.new_window(window);
+            return Handled::Yes;
+        }
+        if let Some(log_in) = cmd.get(ANSWER_CREDENTIALS) {
+            if let Some((window, reply)) = self.login.take() {
+                let credentials = Credentials { username: data.username.clone(), password: data.password.clone() };
+                let _ = reply.send(Some(credentials).filter(|_| *log_in));
+                data.password.clear();
+                ctx.submit_command(commands::CLOSE_WINDOW.to(window));
+            }
+            return Handled::Yes;
+        }
+        return Handled::No;
+    }
+
+    // Closing the login dialog is the same as cancelling it
+    fn window_removed(&mut self, id: WindowId, _data: &mut AppState, _env: &Env, _ctx: &mut DelegateCtx) {
+        if self.login.as_ref().is_some_and(|(window, _)| *window == id) {
+            if let Some((_, reply)) = self.login.take() {
+                let _ = reply.send(None);
+            }
+        }
+    }
 }
 
 fn build_root_widget() -> impl Widget<AppState> {
@@ -70,19 +407,29 @@ fn build_root_widget() -> impl Widget<AppState> {
                     TextBox::new().lens(AppState::url)
                 )
                 .with_child(
-                    Button::new("Go").on_click(|ctx, state: &mut AppState, env| {
-                        let page = load(&state.url);
-                        ctx.new_sub_window(
-                            WindowConfig::default()
-                                .show_titlebar(false)
-                                .window_size(Size::new(500., 500.))
-                                .set_level(WindowLevel::AppWindow),
-                            page,
-                            state.clone(),
-                            env.clone(),
-                        );
+                    Button::new("Go").on_click(|ctx, state: &mut AppState, _env| {
+                        ctx.submit_command(OPEN_URL.with(state.url.clone()));
+                    }),
+                )
+                .with_child(
+                    Button::new("Bookmark").on_click(|ctx, state: &mut AppState, _env| {
+                        ctx.submit_command(OPEN_URL.with(bookmarks::add_url(&state.url)));
                     }),
                 )
+                .with_child(
+                    Button::new("Stop")
+                        .on_click(|ctx, _state: &mut AppState, _env| {
+                            ctx.submit_command(STOP);
+                        })
+                        .disabled_if(|state: &AppState, _env| !state.loading),
+                )
+                .with_child(
+                    Either::new(
+                        |state: &AppState, _env| state.loading,
+                        Spinner::new().fix_size(16., 16.),
+                        Label::new(""),
+                    )
+                )
                 .center()
         );
 }
@@ -95,11 +442,27 @@ fn main() {
         args[1].to_string()
     };
 
-    let state = AppState { url };
+    let state = AppState {
+        url,
+        loading: false,
+        fields: Arc::new(HashMap::new()),
+        username: String::new(),
+        password: String::new(),
+    };
     let window = WindowDesc::new(
         build_root_widget()).title(String::from("Bowser")
     );
-    AppLauncher::with_window(window)
+    let launcher = AppLauncher::with_window(window);
+    let sink = launcher.get_external_handle();
+    let delegate = Delegate {
+        sink: sink.clone(),
+        loads: HashMap::new(),
+        next_id: 0,
+        prompt: Arc::new(DialogPrompt { sink: Mutex::new(sink) }),
+        login: None,
+    };
+    launcher
+        .delegate(delegate)
         .launch(state)
         .expect("failed to launch gui");
 }
@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+use std::{env, fs};
+
+const SETTINGS: &str = "settings.txt";
+
+// Where bowser keeps state between runs: $BOWSER_HOME if set, otherwise
+// ~/.bowser. None when there is no home directory to put it in.
+pub fn dir() -> Option<PathBuf> {
+    let dir = match env::var_os("BOWSER_HOME") {
+        Some(home) => PathBuf::from(home),
+        None => PathBuf::from(env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?).join(".bowser"),
+    };
+    fs::create_dir_all(&dir).ok()?;
+    return Some(dir);
+}
+
+pub fn path(name: &str) -> Option<PathBuf> {
+    return Some(dir()?.join(name));
+}
+
+// A setting changed on about:settings, stored as name=value lines
+pub fn setting(name: &str) -> Option<String> {
+    let text = fs::read_to_string(path(SETTINGS)?).ok()?;
+    return text
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string());
+}
+
+pub fn set_setting(name: &str, value: &str) {
+    let file = match path(SETTINGS) {
+        Some(file) => file,
+        None => return,
+    };
+    let text = fs::read_to_string(&file).unwrap_or_default();
+    let mut lines: Vec<String> = text
+        .lines()
+        .filter(|line| line.split_once('=').is_some_and(|(key, _)| key != name))
+        .map(|line| line.to_string())
+        .collect();
+    lines.push(format!("{}={}", name, value.replace('\n', " ")));
+    if let Err(e) = fs::write(&file, lines.join("\n") + "\n") {
+        println!("Failed to save settings to {}: {}", file.display(), e);
+    }
+}
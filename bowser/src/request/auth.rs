@@ -0,0 +1,478 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::proxy::Proxy;
+use super::{Stores, Url};
+
+// How many times the user is asked before a 401 is shown as is
+const MAX_PROMPTS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    // The user:pass@ part of a url, if it has one
+    pub fn from_url(url: &Url) -> Option<Credentials> {
+        return Some(Credentials { username: url.username()?, password: url.password().unwrap_or_default() });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    // lower case, such as "basic" or "digest"
+    pub scheme: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        return self.params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+    }
+
+    pub fn realm(&self) -> &str {
+        return self.param("realm").unwrap_or("");
+    }
+
+    fn algorithm(&self) -> String {
+        return self.param("algorithm").unwrap_or("MD5").to_uppercase();
+    }
+
+    fn supported(&self) -> bool {
+        match self.scheme.as_str() {
+            "basic" => return true,
+            "digest" => return matches!(self.algorithm().as_str(), "MD5" | "MD5-SESS" | "SHA-256" | "SHA-256-SESS"),
+            _ => return false,
+        }
+    }
+
+    // Digest with SHA-256 beats Digest with MD5, which beats Basic
+    fn strength(&self) -> u8 {
+        match (self.scheme.as_str(), self.algorithm().starts_with("SHA-256")) {
+            ("digest", true) => return 3,
+            ("digest", false) => return 2,
+            _ => return 1,
+        }
+    }
+}
+
+fn read_quoted(input: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    value.push(escaped);
+                }
+            }
+            '"' => { return (value, &input[i + 1..]); }
+            _ => value.push(c),
+        }
+    }
+    return (value, "");
+}
+
+// Parses the challenges in WWW-Authenticate or Proxy-Authenticate values
+// (RFC 9110 11.6.1). One header may hold several challenges, each an auth
+// scheme followed by comma separated name=value parameters.
+pub fn parse_challenges<'a, I: Iterator<Item = &'a str>>(values: I) -> Vec<Challenge> {
+    let mut challenges = Vec::new();
+    for value in values {
+        let mut rest = value.trim_start();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+                continue;
+            }
+            let end = rest.find(|c: char| c.is_whitespace() || c == ',').unwrap_or(rest.len());
+            let mut challenge = Challenge { scheme: rest[..end].to_lowercase(), params: Vec::new() };
+            rest = &rest[end..];
+            loop {
+                rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+                let name_end = rest.find(|c: char| c == '=' || c == ',' || c.is_whitespace()).unwrap_or(rest.len());
+                let after = rest[name_end..].trim_start();
+                // a name without "=" is the scheme of the next challenge
+                if name_end == 0 || !after.starts_with('=') {
+                    break;
+                }
+                let name = rest[..name_end].to_lowercase();
+                rest = after[1..].trim_start();
+                let value = match rest.strip_prefix('"') {
+                    Some(quoted) => {
+                        let (value, after) = read_quoted(quoted);
+                        rest = after;
+                        value
+                    }
+                    None => {
+                        let end = rest.find(|c: char| c == ',' || c.is_whitespace()).unwrap_or(rest.len());
+                        let value = rest[..end].to_string();
+                        rest = &rest[end..];
+                        value
+                    }
+                };
+                challenge.params.push((name, value));
+            }
+            challenges.push(challenge);
+        }
+    }
+    return challenges;
+}
+
+// The strongest challenge we know how to answer
+pub fn best(challenges: &[Challenge]) -> Option<&Challenge> {
+    return challenges.iter().filter(|challenge| challenge.supported()).max_by_key(|challenge| challenge.strength());
+}
+
+fn hex_digest(algorithm: &str, input: &str) -> String {
+    let bytes = if algorithm.starts_with("SHA-256") {
+        Sha256::digest(input.as_bytes()).to_vec()
+    } else {
+        Md5::digest(input.as_bytes()).to_vec()
+    };
+    return bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+}
+
+fn new_cnonce() -> String {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos()).unwrap_or(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    return hex_digest("SHA-256", &format!("{}:{}:{}", nanos, count, std::process::id()))[..16].to_string();
+}
+
+fn quote(value: &str) -> String {
+    return format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+}
+
+fn digest(challenge: &Challenge, credentials: &Credentials, method: &str, uri: &str) -> String {
+    // nonce counts only have to increase, so one counter serves every nonce
+    static NONCE_COUNT: AtomicU32 = AtomicU32::new(0);
+    let nc = NONCE_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    return digest_with(challenge, credentials, method, uri, &new_cnonce(), nc);
+}
+
+// RFC 7616 section 3.4, with the client nonce and nonce count given
+fn digest_with(
+    challenge: &Challenge,
+    credentials: &Credentials,
+    method: &str,
+    uri: &str,
+    cnonce: &str,
+    nc: u32,
+) -> String {
+    let algorithm = challenge.algorithm();
+    let realm = challenge.realm();
+    let nonce = challenge.param("nonce").unwrap_or("");
+    let nc = format!("{:08x}", nc);
+
+    let mut ha1 = hex_digest(&algorithm, &format!("{}:{}:{}", credentials.username, realm, credentials.password));
+    if algorithm.ends_with("-SESS") {
+        ha1 = hex_digest(&algorithm, &format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = hex_digest(&algorithm, &format!("{}:{}", method, uri));
+    let qop = challenge.param("qop").is_some_and(|qop| qop.split(',').any(|option| option.trim() == "auth"));
+    let response = if qop {
+        hex_digest(&algorithm, &format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2))
+    } else {
+        // RFC 2069 compatibility, for servers that offer no qop
+        hex_digest(&algorithm, &format!("{}:{}:{}", ha1, nonce, ha2))
+    };
+
+    let mut header = format!(
+        "Digest username={}, realm={}, nonce={}, uri={}, algorithm={}, response={}",
+        quote(&credentials.username),
+        quote(realm),
+        quote(nonce),
+        quote(uri),
+        challenge.param("algorithm").unwrap_or("MD5"),
+        quote(&response)
+    );
+    if let Some(opaque) = challenge.param("opaque") {
+        header.push_str(&format!(", opaque={}", quote(opaque)));
+    }
+    if qop {
+        header.push_str(&format!(", qop=auth, nc={}, cnonce={}", nc, quote(cnonce)));
+    }
+    return header;
+}
+
+pub fn basic(credentials: &Credentials) -> String {
+    return format!("Basic {}", STANDARD.encode(format!("{}:{}", credentials.username, credentials.password)));
+}
+
+// The Authorization or Proxy-Authorization value answering `challenge` for
+// a request with this method and request target
+pub fn authorization(challenge: &Challenge, credentials: &Credentials, method: &str, uri: &str) -> String {
+    if challenge.scheme == "digest" {
+        return digest(challenge, credentials, method, uri);
+    }
+    return basic(credentials);
+}
+
+// Asks the user for credentials, for a login dialog or the like
+pub trait Prompt: Sync {
+    // `target` is the host asking, `proxy` whether it is a proxy. None when
+    // the user declines.
+    fn ask(&self, target: &str, realm: &str, proxy: bool) -> Option<Credentials>;
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    // the origin of a server, or "proxy host:port"
+    key: String,
+    realm: String,
+    credentials: Credentials,
+    challenge: Challenge,
+    // requests under this path are sent credentials before being asked
+    path: String,
+}
+
+// Credentials that worked, kept for the rest of the session per server or
+// proxy and realm
+#[derive(Default)]
+pub struct CredentialCache {
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl CredentialCache {
+    pub fn new() -> CredentialCache {
+        return CredentialCache::default();
+    }
+
+    pub fn get(&self, key: &str, realm: &str) -> Option<Credentials> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.iter().find(|entry| entry.key == key && entry.realm == realm)?;
+        return Some(entry.credentials.clone());
+    }
+
+    fn set(&self, entry: Entry) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|old| !(old.key == entry.key && old.realm == entry.realm));
+        entries.push(entry);
+    }
+
+    pub fn remove(&self, key: &str, realm: &str) {
+        self.entries.lock().unwrap().retain(|entry| !(entry.key == key && entry.realm == realm));
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    // Credentials to send with a request to `path` before any challenge,
+    // as browsers do under a directory that already asked for them
+    fn preemptive(&self, key: &str, path: &str) -> Option<Entry> {
+        let entries = self.entries.lock().unwrap();
+        return entries
+            .iter()
+            .filter(|entry| entry.key == key && path.starts_with(&entry.path))
+            .max_by_key(|entry| entry.path.len())
+            .cloned();
+    }
+}
+
+pub fn shared() -> &'static CredentialCache {
+    static CACHE: OnceLock<CredentialCache> = OnceLock::new();
+    return CACHE.get_or_init(CredentialCache::new);
+}
+
+// The directory of a path, which credentials are assumed to cover
+fn directory(path: &str) -> String {
+    return path[..path.rfind('/').map(|slash| slash + 1).unwrap_or(0)].to_string();
+}
+
+// One request's dealings with a server or a proxy that wants credentials:
+// what was tried, and what to remember once something works
+pub struct Attempt {
+    key: String,
+    target: String,
+    proxy: bool,
+    from_url: Option<Credentials>,
+    failed: Vec<Credentials>,
+    prompts: usize,
+    stale: bool,
+    pending: Option<Entry>,
+}
+
+impl Attempt {
+    pub fn server(url: &Url) -> Attempt {
+        return Attempt {
+            key: url.origin(),
+            target: url.host_port(),
+            proxy: false,
+            from_url: Credentials::from_url(url),
+            failed: Vec::new(),
+            prompts: 0,
+            stale: false,
+            pending: None,
+        };
+    }
+
+    pub fn proxy(proxy: &Proxy) -> Attempt {
+        let authority = proxy.authority();
+        return Attempt {
+            key: format!("proxy {}", authority),
+            target: authority,
+            proxy: true,
+            from_url: proxy.credentials.clone(),
+            failed: Vec::new(),
+            prompts: 0,
+            stale: false,
+            pending: None,
+        };
+    }
+
+    // Remembered credentials for a request to `url` that has not been
+    // challenged yet
+    pub fn preemptive(&mut self, stores: Stores, url: &Url) -> Option<(Credentials, Challenge)> {
+        let entry = stores.credentials?.preemptive(&self.key, &url.path)?;
+        let answer = (entry.credentials.clone(), entry.challenge.clone());
+        self.pending = Some(entry);
+        return Some(answer);
+    }
+
+    // Picks credentials to answer `challenges` with: those in the url, then
+    // remembered ones for the realm, then whatever the user types. None once
+    // every option has been refused.
+    pub fn answer(&mut self, stores: Stores, url: &Url, challenges: &[Challenge]) -> Option<(Credentials, Challenge)> {
+        let challenge = best(challenges)?.clone();
+        let realm = challenge.realm().to_string();
+        // a stale nonce means the credentials were right but the nonce
+        // expired, so they are worth sending again with the new one, once
+        let stale = challenge.param("stale").is_some_and(|stale| stale.eq_ignore_ascii_case("true")) && !self.stale;
+        self.stale |= stale;
+        if let Some(pending) = self.pending.take() {
+            if !stale {
+                if let Some(cache) = stores.credentials {
+                    cache.remove(&self.key, &pending.realm);
+                }
+                self.failed.push(pending.credentials.clone());
+            } else {
+                self.pending = Some(Entry { challenge: challenge.clone(), ..pending });
+                return self.pending.as_ref().map(|entry| (entry.credentials.clone(), challenge));
+            }
+        }
+
+        let known = self
+            .from_url
+            .clone()
+            .into_iter()
+            .chain(stores.credentials.and_then(|cache| cache.get(&self.key, &realm)))
+            .find(|credentials| !self.failed.contains(credentials));
+        let credentials = match known {
+            Some(credentials) => credentials,
+            None if self.prompts < MAX_PROMPTS => {
+                self.prompts += 1;
+                stores.prompt?.ask(&self.target, &realm, self.proxy)?
+            }
+            None => { return None; }
+        };
+        self.pending = Some(Entry {
+            key: self.key.clone(),
+            realm,
+            credentials: credentials.clone(),
+            challenge: challenge.clone(),
+            // a proxy wants them for every request
+            path: if self.proxy { String::new() } else { directory(&url.path) },
+        });
+        return Some((credentials, challenge));
+    }
+
+    // The last credentials sent were accepted, so keep them for the session
+    pub fn succeeded(&mut self, stores: Stores) {
+        if let (Some(entry), Some(cache)) = (self.pending.take(), stores.credentials) {
+            cache.set(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(scheme: &str, params: &[(&str, &str)]) -> Challenge {
+        let params = params.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        return Challenge { scheme: scheme.to_string(), params };
+    }
+
+    #[test]
+    fn parses_challenges() {
+        let header = "Newauth realm=\"apps\", type=1, title=\"Login to \\\"apps\\\"\", Basic realm=\"simple\"";
+        assert_eq!(
+            parse_challenges([header].into_iter()),
+            [
+                challenge("newauth", &[("realm", "apps"), ("type", "1"), ("title", "Login to \"apps\"")]),
+                challenge("basic", &[("realm", "simple")]),
+            ]
+        );
+        // one challenge per header, with a bare scheme and stray commas
+        let headers = ["Negotiate", ", Digest realm=\"a\" , nonce=n,,qop=\"auth,auth-int\""];
+        assert_eq!(
+            parse_challenges(headers.into_iter()),
+            [
+                challenge("negotiate", &[]),
+                challenge("digest", &[("realm", "a"), ("nonce", "n"), ("qop", "auth,auth-int")]),
+            ]
+        );
+        assert_eq!(parse_challenges(["Basic realm=\"unterminated"].into_iter())[0].realm(), "unterminated");
+    }
+
+    #[test]
+    fn picks_the_strongest() {
+        let basic = challenge("basic", &[("realm", "r")]);
+        let md5 = challenge("digest", &[("realm", "r")]);
+        let sha = challenge("digest", &[("realm", "r"), ("algorithm", "SHA-256")]);
+        let unknown = challenge("digest", &[("algorithm", "SHA-512-256")]);
+        let negotiate = challenge("negotiate", &[]);
+        assert_eq!(best(&[basic.clone(), sha.clone(), md5.clone()]), Some(&sha));
+        assert_eq!(best(&[basic.clone(), md5.clone()]), Some(&md5));
+        assert_eq!(best(&[unknown.clone(), negotiate.clone(), basic.clone()]), Some(&basic));
+        assert_eq!(best(&[unknown, negotiate]), None);
+    }
+
+    #[test]
+    fn digest_matches_the_rfc() {
+        // RFC 7616 section 3.9.1
+        let credentials = Credentials { username: "Mufasa".to_string(), password: "Circle of Life".to_string() };
+        let params = [
+            ("realm", "http-auth@example.org"),
+            ("qop", "auth, auth-int"),
+            ("nonce", "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v"),
+            ("opaque", "FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS"),
+        ];
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let md5 = digest_with(&challenge("digest", &params), &credentials, "GET", "/dir/index.html", cnonce, 1);
+        assert_eq!(
+            md5,
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", algorithm=MD5, \
+             response=\"8ca523f5e9506fed4657c9700eebdbec\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\", \
+             qop=auth, nc=00000001, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\""
+        );
+        let sha = challenge("digest", &[&params[..], &[("algorithm", "SHA-256")]].concat());
+        let sha = digest_with(&sha, &credentials, "GET", "/dir/index.html", cnonce, 1);
+        assert!(
+            sha.contains(" response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""),
+            "{}",
+            sha
+        );
+    }
+
+    #[test]
+    fn basic_credentials() {
+        // RFC 7617 section 2
+        let credentials = Credentials { username: "Aladdin".to_string(), password: "open sesame".to_string() };
+        assert_eq!(basic(&credentials), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        assert_eq!(
+            authorization(&challenge("basic", &[("realm", "r")]), &credentials, "GET", "/"),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use super::about;
+use super::date::{format_timestamp, now};
+use super::form::{urldecode, urlencode, FormValue};
+use crate::html::escape;
+use crate::profile;
+
+#[derive(Debug, Clone)]
+pub struct Bookmark {
+    pub url: String,
+    pub added: i64,
+}
+
+// Saved urls in the order they were added, persisted to a file in the
+// profile directory
+pub struct Bookmarks {
+    list: Mutex<Vec<Bookmark>>,
+    file: Option<PathBuf>,
+}
+
+impl Bookmarks {
+    pub fn new(file: Option<PathBuf>) -> Bookmarks {
+        let list = file.as_ref().and_then(|file| fs::read_to_string(file).ok()).map(|text| load(&text));
+        return Bookmarks { list: Mutex::new(list.unwrap_or_default()), file };
+    }
+
+    // Adding a url that is already bookmarked does nothing
+    pub fn add(&self, url: &str) {
+        let url = url.trim();
+        let mut list = self.list.lock().unwrap();
+        if url.is_empty() || list.iter().any(|bookmark| bookmark.url == url) {
+            return;
+        }
+        list.push(Bookmark { url: url.to_string(), added: now() });
+        self.save(&list);
+    }
+
+    pub fn remove(&self, url: &str) {
+        let mut list = self.list.lock().unwrap();
+        list.retain(|bookmark| bookmark.url != url);
+        self.save(&list);
+    }
+
+    pub fn all(&self) -> Vec<Bookmark> {
+        return self.list.lock().unwrap().clone();
+    }
+
+    fn save(&self, list: &[Bookmark]) {
+        if let Some(file) = &self.file {
+            let text: String = list.iter().map(|bookmark| format!("{}\t{}\n", bookmark.added, bookmark.url)).collect();
+            if let Err(e) = fs::write(file, text) {
+                println!("Failed to save bookmarks to {}: {}", file.display(), e);
+            }
+        }
+    }
+}
+
+// One bookmark per line, the time it was added then the url, tab separated
+fn load(text: &str) -> Vec<Bookmark> {
+    return text
+        .lines()
+        .filter_map(|line| {
+            let (added, url) = line.split_once('\t')?;
+            return Some(Bookmark { url: url.to_string(), added: added.parse().ok()? });
+        })
+        .collect();
+}
+
+pub fn shared() -> &'static Bookmarks {
+    static BOOKMARKS: OnceLock<Bookmarks> = OnceLock::new();
+    return BOOKMARKS.get_or_init(|| Bookmarks::new(profile::path("bookmarks.txt")));
+}
+
+// The url of the about:bookmarks page that bookmarks `url`
+pub fn add_url(url: &str) -> String {
+    return about::action_url("bookmarks", &urlencode(&[("add".to_string(), FormValue::Text(url.to_string()))]));
+}
+
+// The about:bookmarks page. ?add=url and ?remove=url change the list before
+// it is drawn, and the form at the top submits an add.
+pub fn page(bookmarks: &Bookmarks, query: Option<&str>) -> String {
+    for (action, url) in urldecode(query.unwrap_or("")) {
+        match action.as_str() {
+            "add" => bookmarks.add(&url),
+            "remove" => bookmarks.remove(&url),
+            _ => {}
+        }
+    }
+
+    let mut html = String::from("<html><body><h1>Bookmarks</h1>");
+    html.push_str(&format!(
+        "<form action=\"about:bookmarks\">{}<input name=\"add\"> <input type=\"submit\" value=\"Add\"></form>",
+        about::token_input()
+    ));
+    let list = bookmarks.all();
+    if list.is_empty() {
+        html.push_str("<p>Nothing is bookmarked.</p></body></html>");
+        return html;
+    }
+    html.push_str("<pre>");
+    for bookmark in &list {
+        let url = escape(&bookmark.url);
+        let remove = urlencode(&[("remove".to_string(), FormValue::Text(bookmark.url.clone()))]);
+        html.push_str(&format!(
+            "{}  <a href=\"{}\">{}</a>  <a href=\"{}\">remove</a>\n",
+            format_timestamp(bookmark.added),
+            url,
+            url,
+            escape(&about::action_url("bookmarks", &remove))
+        ));
+    }
+    html.push_str("</pre></body></html>");
+    return html;
+}
@@ -0,0 +1,724 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use super::about;
+use super::date::{now, parse_http_date};
+use super::{http, HeaderMap, RequestError, Response, Url};
+use crate::html::escape;
+use crate::profile;
+
+const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+const DISK_LIMIT: u64 = 64 * 1024 * 1024;
+
+// Statuses that can be cached on heuristic freshness alone (RFC 9110 15.1)
+const HEURISTIC_STATUSES: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+// Response headers that are not stored. The body is kept decoded and whole,
+// so its framing and codings no longer apply, and cookies belong in the jar.
+// A 304 cannot bring them back either.
+const UNSTORED: [&str; 4] = ["content-encoding", "content-length", "set-cookie", "transfer-encoding"];
+
+#[derive(Default)]
+struct Directives {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    max_age: Option<i64>,
+}
+
+impl Directives {
+    fn of(headers: &HeaderMap) -> Directives {
+        let mut directives = Directives::default();
+        let cache_control = match headers.get_joined("cache-control") {
+            Some(cache_control) => cache_control,
+            None => {
+                // HTTP/1.0 caches only understood Pragma
+                let pragma = headers.get_joined("pragma").unwrap_or_default();
+                directives.no_cache = pragma.split(',').any(|p| p.trim().eq_ignore_ascii_case("no-cache"));
+                return directives;
+            }
+        };
+        for directive in cache_control.split(',') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim().to_lowercase(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim().to_lowercase(), None),
+            };
+            match name.as_str() {
+                "no-store" => directives.no_store = true,
+                // a no-cache naming specific fields is treated as plain no-cache
+                "no-cache" => directives.no_cache = true,
+                "private" => directives.private = true,
+                "max-age" => directives.max_age = value.and_then(|v| v.parse().ok()).or(Some(0)),
+                _ => {}
+            }
+        }
+        return directives;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub url: String,
+    pub status: u16,
+    pub reason: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    // the request headers named by Vary and the values they had
+    vary: Vec<(String, String)>,
+    request_time: i64,
+    response_time: i64,
+    last_used: u64,
+}
+
+impl Entry {
+    fn date(&self) -> i64 {
+        return self.headers.get("date").and_then(parse_http_date).unwrap_or(self.response_time);
+    }
+
+    // RFC 9111 section 4.2.1
+    fn freshness_lifetime(&self) -> i64 {
+        if let Some(max_age) = Directives::of(&self.headers).max_age {
+            return max_age;
+        }
+        if let Some(expires) = self.headers.get("expires") {
+            // an unparseable Expires means already expired
+            return parse_http_date(expires).map(|expires| expires - self.date()).unwrap_or(0);
+        }
+        if HEURISTIC_STATUSES.contains(&self.status) {
+            if let Some(modified) = self.headers.get("last-modified").and_then(parse_http_date) {
+                return (self.date() - modified).max(0) / 10;
+            }
+        }
+        return 0;
+    }
+
+    // RFC 9111 section 4.2.3
+    fn current_age(&self, now: i64) -> i64 {
+        let apparent_age = (self.response_time - self.date()).max(0);
+        let age = self.headers.get("age").and_then(|age| age.parse::<i64>().ok()).unwrap_or(0);
+        let corrected_age = age + (self.response_time - self.request_time);
+        return apparent_age.max(corrected_age) + (now - self.response_time);
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        return !Directives::of(&self.headers).no_cache && self.freshness_lifetime() > self.current_age(now());
+    }
+
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        return self.vary.iter().all(|(name, value)| headers.get_joined(name).unwrap_or_default() == *value);
+    }
+
+    fn size(&self) -> usize {
+        return self.body.len() + self.headers.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>();
+    }
+
+    fn response(&self, url: &Url) -> Response {
+        return Response {
+            status: self.status,
+            reason: self.reason.clone(),
+            headers: self.headers.clone(),
+            decoded_length: self.body.len(),
+            body: self.body.clone(),
+            final_url: url.clone(),
+            streamed: false,
+        };
+    }
+
+    // Folds in the headers of a 304 that revalidated this entry
+    fn refresh(&mut self, headers: &HeaderMap, request_time: i64, response_time: i64) {
+        for (name, _) in headers.iter() {
+            if !is_unstored(name) {
+                self.headers.remove(name);
+            }
+        }
+        for (name, value) in headers.iter() {
+            if !is_unstored(name) {
+                self.headers.append(name, value);
+            }
+        }
+        self.request_time = request_time;
+        self.response_time = response_time;
+    }
+}
+
+fn is_unstored(name: &str) -> bool {
+    return UNSTORED.contains(&name.to_lowercase().as_str());
+}
+
+// The headers to store with a decoded body
+fn stored_headers(headers: &HeaderMap, body: &[u8]) -> HeaderMap {
+    let mut stored = HeaderMap::new();
+    for (name, value) in headers.iter().filter(|(name, _)| !is_unstored(name)) {
+        stored.append(name, value);
+    }
+    stored.insert("Content-Length", &body.len().to_string());
+    return stored;
+}
+
+fn key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.fragment = None;
+    return url.to_string();
+}
+
+// Whether a response to a GET with these request headers may be stored
+fn storable(response: &Response, headers: &HeaderMap) -> bool {
+    // a body streamed to a sink was never kept
+    if response.streamed {
+        return false;
+    }
+    let directives = Directives::of(&response.headers);
+    if directives.no_store || Directives::of(headers).no_store {
+        return false;
+    }
+    if response.headers.get_joined("vary").is_some_and(|vary| vary.split(',').any(|v| v.trim() == "*")) {
+        return false;
+    }
+    let explicit = directives.max_age.is_some() || response.headers.contains_key("expires");
+    let validator = response.headers.contains_key("etag") || response.headers.contains_key("last-modified");
+    return explicit || (validator && HEURISTIC_STATUSES.contains(&response.status));
+}
+
+// FNV-1a, to turn a url into a file name that is stable between runs
+fn file_name(key: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return format!("{:016x}", hash);
+}
+
+// Entries are stored as a block of metadata headers followed by the
+// response exactly as an HTTP/1.1 message, with the body already decoded
+fn write_entry(path: &Path, entry: &Entry) -> std::io::Result<()> {
+    let partial = path.with_extension("part");
+    let mut file = BufWriter::new(File::create(&partial)?);
+    write!(file, "Url: {}\r\n", entry.url)?;
+    write!(file, "Request-Time: {}\r\n", entry.request_time)?;
+    write!(file, "Response-Time: {}\r\n", entry.response_time)?;
+    for (name, value) in &entry.vary {
+        write!(file, "Vary-{}: {}\r\n", name, value)?;
+    }
+    write!(file, "\r\nHTTP/1.1 {} {}\r\n", entry.status, entry.reason)?;
+    for (name, value) in entry.headers.iter() {
+        write!(file, "{}: {}\r\n", name, value)?;
+    }
+    file.write_all(b"\r\n")?;
+    file.write_all(&entry.body)?;
+    file.into_inner()?.sync_all()?;
+    return fs::rename(partial, path);
+}
+
+fn read_entry(path: &Path) -> Option<Entry> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let meta = http::read_headers(&mut reader).ok()?;
+    let (_, status, reason, headers) = http::read_head(&mut reader).ok()?;
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body).ok()?;
+    let vary = meta
+        .iter()
+        .filter_map(|(name, value)| Some((name.strip_prefix("Vary-")?.to_string(), value.to_string())))
+        .collect();
+    return Some(Entry {
+        url: meta.get("url")?.to_string(),
+        status,
+        reason,
+        headers,
+        body,
+        vary,
+        request_time: meta.get("request-time")?.parse().ok()?,
+        response_time: meta.get("response-time")?.parse().ok()?,
+        last_used: 0,
+    });
+}
+
+// Deletes the least recently used files until the directory fits the limit
+fn evict_disk(dir: &Path, limit: u64) {
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let meta = entry.metadata().ok()?;
+                return Some((meta.modified().ok()?, meta.len(), entry.path()));
+            })
+            .collect(),
+        Err(_) => return,
+    };
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    files.sort();
+    for (_, len, path) in files {
+        if total <= limit {
+            break;
+        }
+        if fs::remove_file(path).is_ok() {
+            total -= len;
+        }
+    }
+}
+
+struct Store {
+    memory: HashMap<String, Entry>,
+    size: usize,
+    clock: u64,
+}
+
+// Responses kept in memory, backed by a directory of entries that survives
+// restarts. Both are bounded and drop the least recently used entries first.
+pub struct Cache {
+    store: Mutex<Store>,
+    dir: Option<PathBuf>,
+    memory_limit: usize,
+    disk_limit: u64,
+}
+
+impl Cache {
+    pub fn new(dir: Option<PathBuf>) -> Cache {
+        if let Some(dir) = &dir {
+            let _ = fs::create_dir_all(dir);
+        }
+        return Cache {
+            store: Mutex::new(Store { memory: HashMap::new(), size: 0, clock: 0 }),
+            dir,
+            memory_limit: MEMORY_LIMIT,
+            disk_limit: DISK_LIMIT,
+        };
+    }
+
+    fn lookup(&self, key: &str, headers: &HeaderMap) -> Option<Entry> {
+        let mut store = self.store.lock().unwrap();
+        store.clock += 1;
+        let clock = store.clock;
+        if let Some(entry) = store.memory.get_mut(key) {
+            entry.last_used = clock;
+            return Some(entry.clone()).filter(|entry| entry.matches(headers));
+        }
+        drop(store);
+
+        let path = self.dir.as_ref()?.join(file_name(key));
+        // a hash collision reads back some other url
+        let entry = read_entry(&path).filter(|entry| entry.url == key)?;
+        if let Ok(file) = File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        self.remember(entry.clone());
+        return Some(entry).filter(|entry| entry.matches(headers));
+    }
+
+    fn remember(&self, mut entry: Entry) {
+        let mut store = self.store.lock().unwrap();
+        if let Some(old) = store.memory.remove(&entry.url) {
+            store.size -= old.size();
+        }
+        if entry.size() > self.memory_limit {
+            return;
+        }
+        store.clock += 1;
+        entry.last_used = store.clock;
+        store.size += entry.size();
+        store.memory.insert(entry.url.clone(), entry);
+        while store.size > self.memory_limit {
+            let oldest = store.memory.values().min_by_key(|entry| entry.last_used).map(|entry| entry.url.clone());
+            match oldest.and_then(|url| store.memory.remove(&url)) {
+                Some(evicted) => store.size -= evicted.size(),
+                None => break,
+            }
+        }
+    }
+
+    // Private responses are only meant for this user, so they are kept in
+    // memory and never written to disk
+    fn store(&self, entry: Entry) {
+        let private = Directives::of(&entry.headers).private;
+        if let Some(dir) = &self.dir {
+            let path = dir.join(file_name(&entry.url));
+            if private {
+                let _ = fs::remove_file(path);
+            } else if let Err(e) = write_entry(&path, &entry) {
+                println!("Failed to write cache entry {}: {}", path.display(), e);
+            } else {
+                evict_disk(dir, self.disk_limit);
+            }
+        }
+        self.remember(entry);
+    }
+
+    pub fn remove(&self, url: &Url) {
+        let key = key(url);
+        let mut store = self.store.lock().unwrap();
+        if let Some(old) = store.memory.remove(&key) {
+            store.size -= old.size();
+        }
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_file(dir.join(file_name(&key)));
+        }
+    }
+
+    // Everything stored, in memory or on disk, sorted by url
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut entries: Vec<Entry> = self.store.lock().unwrap().memory.values().cloned().collect();
+        let files = self.dir.as_ref().and_then(|dir| fs::read_dir(dir).ok());
+        for file in files.into_iter().flatten().filter_map(|file| file.ok()) {
+            // skip entries still being written, which end in .part
+            if file.path().extension().is_some() {
+                continue;
+            }
+            if let Some(entry) = read_entry(&file.path()) {
+                if !entries.iter().any(|known| known.url == entry.url) {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.url.cmp(&b.url));
+        return entries;
+    }
+
+    pub fn clear(&self) {
+        let mut store = self.store.lock().unwrap();
+        store.memory.clear();
+        store.size = 0;
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+            let _ = fs::create_dir_all(dir);
+        }
+    }
+
+    // Answers a GET from the cache when a fresh response is stored, and
+    // otherwise calls `send` with the request headers to use, adding
+    // validators so a stored response can be reused on a 304
+    pub fn fetch<F>(&self, url: &Url, headers: &HeaderMap, send: F) -> Result<Response, RequestError>
+    where
+        F: FnOnce(&HeaderMap) -> Result<Response, RequestError>,
+    {
+        let key = key(url);
+        let request = Directives::of(headers);
+        let stored = if request.no_store { None } else { self.lookup(&key, headers) };
+
+        let mut conditional = headers.clone();
+        if let Some(entry) = &stored {
+            if !request.no_cache && entry.is_fresh() {
+                return Ok(entry.response(url));
+            }
+            if !headers.contains_key("if-none-match") && !headers.contains_key("if-modified-since") {
+                if let Some(etag) = entry.headers.get("etag") {
+                    conditional.insert("If-None-Match", etag);
+                }
+                if let Some(modified) = entry.headers.get("last-modified") {
+                    conditional.insert("If-Modified-Since", modified);
+                }
+            }
+        }
+
+        let request_time = now();
+        let response = send(&conditional)?;
+        let response_time = now();
+
+        if let (304, Some(mut entry)) = (response.status, stored) {
+            entry.refresh(&response.headers, request_time, response_time);
+            self.store(entry.clone());
+            return Ok(entry.response(url));
+        }
+        if storable(&response, headers) {
+            let vary = response
+                .headers
+                .get_joined("vary")
+                .unwrap_or_default()
+                .split(',')
+                .map(|name| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    let value = headers.get_joined(&name).unwrap_or_default();
+                    return (name, value);
+                })
+                .collect();
+            self.store(Entry {
+                url: key,
+                status: response.status,
+                reason: response.reason.clone(),
+                headers: stored_headers(&response.headers, &response.body),
+                body: response.body.clone(),
+                vary,
+                request_time,
+                response_time,
+                last_used: 0,
+            });
+        } else if Directives::of(&response.headers).no_store {
+            self.remove(url);
+        }
+        return Ok(response);
+    }
+
+    // Drops what is stored for a url that a successful unsafe request may
+    // have changed, along with the urls its response says were changed on
+    // the same origin (RFC 9111 4.4)
+    pub fn invalidate(&self, method: &str, url: &Url, response: &Response) {
+        if matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE") || response.status >= 400 {
+            return;
+        }
+        self.remove(url);
+        for name in ["location", "content-location"] {
+            if let Some(changed) = response.headers.get(name).and_then(|value| url.join(value).ok()) {
+                if changed.origin() == url.origin() {
+                    self.remove(&changed);
+                }
+            }
+        }
+    }
+}
+
+// The cache shared by every request, stored in the profile directory
+pub fn shared() -> &'static Cache {
+    static CACHE: OnceLock<Cache> = OnceLock::new();
+    return CACHE.get_or_init(|| Cache::new(profile::path("cache")));
+}
+
+// The about:cache page, one line per stored response. ?clear empties the
+// cache first.
+pub fn page(cache: &Cache, query: Option<&str>) -> String {
+    if query.is_some_and(|query| query.split('&').any(|action| action == "clear")) {
+        cache.clear();
+    }
+    let entries = cache.entries();
+    let mut html = String::from("<html><body><h1>Cache</h1>");
+    if entries.is_empty() {
+        html.push_str("<p>Nothing is cached.</p></body></html>");
+        return html;
+    }
+    let total: usize = entries.iter().map(|entry| entry.body.len()).sum();
+    html.push_str(&format!(
+        "<p>{} responses, {} KiB. <a href=\"{}\">Clear cache</a></p><pre>",
+        entries.len(),
+        total / 1024,
+        escape(&about::action_url("cache", "clear"))
+    ));
+    for entry in &entries {
+        let state = if entry.is_fresh() { "fresh" } else { "stale" };
+        let private = if Directives::of(&entry.headers).private { " private" } else { "" };
+        html.push_str(&format!(
+            "{} {:>8}  {}{}  <a href=\"{}\">{}</a>\n",
+            entry.status,
+            entry.body.len(),
+            state,
+            private,
+            escape(&entry.url),
+            escape(&entry.url)
+        ));
+    }
+    html.push_str("</pre></body></html>");
+    return html;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::env;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn canned(status: u16, headers: &[(&str, &str)], body: &[u8]) -> Response {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(name, value);
+        }
+        let mut response = Response::ok(&Url::parse("http://example.com/").unwrap(), map, body.to_vec());
+        response.status = status;
+        return response;
+    }
+
+    // Fetches `url` through the cache, answering with `reply` if the cache
+    // sends the request on. Returns the response and the request headers
+    // sent, or None when it came from the cache.
+    fn fetch(cache: &Cache, url: &str, headers: &[(&str, &str)], reply: Response) -> (Response, Option<HeaderMap>) {
+        let mut request = HeaderMap::new();
+        for (name, value) in headers {
+            request.append(name, value);
+        }
+        let sent = Cell::new(None);
+        let response = cache
+            .fetch(&Url::parse(url).unwrap(), &request, |headers| {
+                sent.set(Some(headers.clone()));
+                return Ok(reply);
+            })
+            .unwrap();
+        return (response, sent.take());
+    }
+
+    fn unused() -> Response {
+        return canned(500, &[], b"not sent");
+    }
+
+    #[test]
+    fn freshness() {
+        let cache = Cache::new(None);
+        let url = "http://example.com/fresh";
+        let (first, sent) = fetch(&cache, url, &[], canned(200, &[("Cache-Control", "max-age=60")], b"one"));
+        assert!(sent.is_some());
+        assert_eq!(first.body, b"one");
+        let (again, sent) = fetch(&cache, url, &[], unused());
+        assert!(sent.is_none());
+        assert_eq!(again.body, b"one");
+
+        // the request can ask to skip a fresh entry
+        let (_, sent) = fetch(&cache, url, &[("Cache-Control", "no-cache")], canned(200, &[], b"two"));
+        assert!(sent.is_some());
+
+        for (headers, fresh) in [
+            (&[("Cache-Control", "max-age=60"), ("Age", "30")][..], true),
+            (&[("Cache-Control", "max-age=60"), ("Age", "120")], false),
+            (&[("Expires", "Thu, 01 Jan 2099 00:00:00 GMT")], true),
+            (&[("Expires", "Thu, 01 Jan 1998 00:00:00 GMT")], false),
+            (&[("Expires", "not a date")], false),
+            // max-age wins over Expires
+            (&[("Cache-Control", "max-age=60"), ("Expires", "Thu, 01 Jan 1998 00:00:00 GMT")], true),
+            // an old Date makes the response old already
+            (&[("Date", "Thu, 01 Jan 1998 00:00:00 GMT"), ("Expires", "Thu, 01 Jan 1998 01:00:00 GMT")], false),
+            (&[("Cache-Control", "max-age=60, no-cache")], false),
+            (&[("Last-Modified", "Thu, 01 Jan 1998 00:00:00 GMT")], true),
+        ] {
+            let cache = Cache::new(None);
+            fetch(&cache, url, &[], canned(200, headers, b"body"));
+            let (_, sent) = fetch(&cache, url, &[], canned(200, &[], b"body"));
+            assert_eq!(sent.is_none(), fresh, "{:?}", headers);
+        }
+    }
+
+    #[test]
+    fn not_stored() {
+        for headers in [
+            &[("Cache-Control", "no-store, max-age=60")][..],
+            &[("Cache-Control", "max-age=60"), ("Vary", "*")],
+            // nothing to say how long it stays fresh, nor to revalidate it
+            &[],
+        ] {
+            let cache = Cache::new(None);
+            fetch(&cache, "http://example.com/", &[], canned(200, headers, b"body"));
+            assert!(cache.entries().is_empty(), "{:?}", headers);
+        }
+        let cache = Cache::new(None);
+        let fresh = canned(200, &[("Cache-Control", "max-age=60")], b"");
+        fetch(&cache, "http://example.com/", &[("Cache-Control", "no-store")], fresh);
+        assert!(cache.entries().is_empty());
+    }
+
+    #[test]
+    fn stores_what_describes_the_body() {
+        let cache = Cache::new(None);
+        let headers = [
+            ("Cache-Control", "max-age=60"),
+            ("Content-Encoding", "gzip"),
+            ("Content-Length", "31"),
+            ("Set-Cookie", "session=secret"),
+            ("Content-Type", "text/plain"),
+        ];
+        fetch(&cache, "http://example.com/", &[], canned(200, &headers, b"decoded"));
+        let (hit, sent) = fetch(&cache, "http://example.com/", &[], unused());
+        assert!(sent.is_none());
+        assert_eq!(hit.headers.get("content-encoding"), None);
+        assert_eq!(hit.headers.get("set-cookie"), None);
+        assert_eq!(hit.headers.get("content-length"), Some("7"));
+        assert_eq!(hit.headers.get("content-type"), Some("text/plain"));
+    }
+
+    #[test]
+    fn revalidation() {
+        let cache = Cache::new(None);
+        let url = "http://example.com/page";
+        let stored =
+            [("ETag", "\"v1\""), ("Content-Type", "text/plain"), ("X-Version", "1"), ("Cache-Control", "max-age=0")];
+        fetch(&cache, url, &[], canned(200, &stored, b"body"));
+
+        let refreshed =
+            [("ETag", "\"v1\""), ("X-Version", "2"), ("Cache-Control", "max-age=60"), ("Content-Length", "0")];
+        let (response, sent) = fetch(&cache, url, &[], canned(304, &refreshed, b""));
+        assert_eq!(sent.unwrap().get("if-none-match"), Some("\"v1\""));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"body");
+        assert_eq!(response.headers.get("x-version"), Some("2"));
+        assert_eq!(response.headers.get("content-type"), Some("text/plain"));
+        // the length is still the stored body's
+        assert_eq!(response.headers.get("content-length"), Some("4"));
+        // and the refreshed max-age makes it fresh again
+        assert!(fetch(&cache, url, &[], unused()).1.is_none());
+
+        // Last-Modified is sent back as If-Modified-Since
+        let cache = Cache::new(None);
+        fetch(&cache, url, &[], canned(200, &[("Last-Modified", "Thu, 01 Jan 2099 00:00:00 GMT")], b""));
+        let (_, sent) = fetch(&cache, url, &[], canned(200, &[], b""));
+        assert_eq!(sent.unwrap().get("if-modified-since"), Some("Thu, 01 Jan 2099 00:00:00 GMT"));
+    }
+
+    #[test]
+    fn vary() {
+        let cache = Cache::new(None);
+        let url = "http://example.com/";
+        let reply = |body: &[u8]| canned(200, &[("Cache-Control", "max-age=60"), ("Vary", "Accept-Language")], body);
+        fetch(&cache, url, &[("Accept-Language", "en")], reply(b"hello"));
+        let (hit, sent) = fetch(&cache, url, &[("Accept-Language", "en")], unused());
+        assert!(sent.is_none());
+        assert_eq!(hit.body, b"hello");
+        let (miss, sent) = fetch(&cache, url, &[("Accept-Language", "fr")], reply(b"bonjour"));
+        assert!(sent.is_some());
+        assert_eq!(miss.body, b"bonjour");
+        assert!(fetch(&cache, url, &[], reply(b"")).1.is_some());
+    }
+
+    #[test]
+    fn invalidation() {
+        let cache = Cache::new(None);
+        let fresh = || canned(200, &[("Cache-Control", "max-age=60")], b"");
+        for url in ["http://example.com/a", "http://example.com/b", "http://other.example/c"] {
+            fetch(&cache, url, &[], fresh());
+        }
+        let url = Url::parse("http://example.com/a").unwrap();
+        cache.invalidate("GET", &url, &canned(200, &[], b""));
+        cache.invalidate("POST", &url, &canned(500, &[], b""));
+        assert_eq!(cache.entries().len(), 3);
+        cache.invalidate("POST", &url, &canned(303, &[("Location", "/b")], b""));
+        assert_eq!(cache.entries().len(), 1);
+        // another origin's entry is left alone
+        let deleted = canned(204, &[("Location", "http://other.example/c")], b"");
+        cache.invalidate("DELETE", &Url::parse("http://example.com/").unwrap(), &deleted);
+        assert_eq!(cache.entries().len(), 1);
+    }
+
+    #[test]
+    fn eviction() {
+        let mut cache = Cache::new(None);
+        let fresh = |body: &[u8]| canned(200, &[("Cache-Control", "max-age=60")], body);
+        let body = [b'x'; 100];
+        // room for two entries
+        cache.memory_limit = 2 * (body.len() + 64);
+        fetch(&cache, "http://example.com/1", &[], fresh(&body));
+        fetch(&cache, "http://example.com/2", &[], fresh(&body));
+        fetch(&cache, "http://example.com/1", &[], unused());
+        fetch(&cache, "http://example.com/3", &[], fresh(&body));
+        let urls: Vec<String> = cache.entries().into_iter().map(|entry| entry.url).collect();
+        // 2 was used least recently
+        assert_eq!(urls, ["http://example.com/1", "http://example.com/3"]);
+
+        // the same goes for the directory
+        let dir = env::temp_dir().join(format!("bowser-test-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut cache = Cache::new(Some(dir.clone()));
+        cache.memory_limit = 0;
+        cache.disk_limit = 700;
+        for (i, url) in ["http://example.com/1", "http://example.com/2", "http://example.com/3"].iter().enumerate() {
+            fetch(&cache, url, &[], fresh(&body));
+            // modification times order the files
+            let path = dir.join(file_name(url));
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(i as u64 + 1);
+            File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        }
+        let urls: Vec<String> = cache.entries().into_iter().map(|entry| entry.url).collect();
+        assert_eq!(urls, ["http://example.com/2", "http://example.com/3"]);
+        // entries come back from disk
+        let cache = Cache::new(Some(dir.clone()));
+        assert_eq!(fetch(&cache, "http://example.com/3", &[], unused()).0.body, body);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
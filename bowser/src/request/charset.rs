@@ -0,0 +1,201 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+use std::str;
+
+use super::mime;
+
+// The WHATWG encoding sniffing algorithm only looks this far into the body
+const PRESCAN_LENGTH: usize = 1024;
+
+// Finds the value of a `charset=` parameter, as in a Content-Type header or
+// the content attribute of a <meta http-equiv> tag
+fn charset_param(value: &str) -> Option<&str> {
+    let lower = value.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find("charset") {
+        from += found + "charset".len();
+        let rest = match value[from..].trim_start().strip_prefix('=') {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            return rest[1..].split(quote).next().filter(|label| !label.is_empty());
+        }
+        return rest
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .next()
+            .filter(|label| !label.is_empty());
+    }
+    return None;
+}
+
+// Reads the attribute of a tag starting at `i`, returning its lowercased
+// name and value with the position after it, or None at the end of the tag
+fn attribute(bytes: &[u8], mut i: usize) -> Option<(String, String, usize)> {
+    while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+        i += 1;
+    }
+    if i >= bytes.len() || bytes[i] == b'>' {
+        return None;
+    }
+    let start = i;
+    i += 1;
+    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !matches!(bytes[i], b'=' | b'>' | b'/') {
+        i += 1;
+    }
+    let name = String::from_utf8_lossy(&bytes[start..i]).to_ascii_lowercase();
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    if i >= bytes.len() || bytes[i] != b'=' {
+        return Some((name, String::new(), i));
+    }
+    i += 1;
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    let value_start;
+    let value_end;
+    if i < bytes.len() && (bytes[i] == b'"' || bytes[i] == b'\'') {
+        let quote = bytes[i];
+        value_start = i + 1;
+        i = value_start;
+        while i < bytes.len() && bytes[i] != quote {
+            i += 1;
+        }
+        value_end = i;
+        i += 1;
+    } else {
+        value_start = i;
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+            i += 1;
+        }
+        value_end = i;
+    }
+    let value = String::from_utf8_lossy(&bytes[value_start..value_end.min(bytes.len())]).to_string();
+    return Some((name, value, i));
+}
+
+// Looks for <meta charset> or <meta http-equiv="content-type"> near the
+// start of an HTML document, a simplified form of the WHATWG prescan
+fn prescan(body: &[u8]) -> Option<&'static Encoding> {
+    let bytes = &body[..body.len().min(PRESCAN_LENGTH)];
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if rest.starts_with(b"<!--") {
+            i += rest.windows(3).position(|window| window == b"-->").map(|end| end + 3).unwrap_or(rest.len());
+            continue;
+        }
+        let is_meta = rest.len() > 5
+            && rest[..5].eq_ignore_ascii_case(b"<meta")
+            && (rest[5].is_ascii_whitespace() || rest[5] == b'/');
+        if !is_meta {
+            i += 1;
+            continue;
+        }
+
+        let (mut charset, mut http_equiv, mut content) = (None, false, None);
+        let mut at = i + 5;
+        while let Some((name, value, next)) = attribute(bytes, at) {
+            match name.as_str() {
+                "charset" if charset.is_none() => charset = Some(value),
+                "http-equiv" => http_equiv = value.eq_ignore_ascii_case("content-type"),
+                "content" if content.is_none() => content = Some(value),
+                _ => {}
+            }
+            at = next;
+        }
+        let label = match (charset, content) {
+            (Some(charset), _) => Some(charset),
+            (None, Some(content)) if http_equiv => charset_param(&content).map(|label| label.to_string()),
+            _ => None,
+        };
+        if let Some(encoding) = label.and_then(|label| Encoding::for_label(label.trim().as_bytes())) {
+            // a document that could be prescanned as ASCII is not UTF-16
+            if encoding == X_USER_DEFINED {
+                return Some(WINDOWS_1252);
+            }
+            return Some(encoding.output_encoding());
+        }
+        i = at;
+    }
+    return None;
+}
+
+// Picks the encoding of a response body: a byte order mark wins, then the
+// charset of the Content-Type, then for HTML a <meta> tag. Unlabelled bodies
+// are UTF-8 if they decode as such and windows-1252 otherwise.
+pub fn detect(content_type: &str, body: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+    if let Some(encoding) = charset_param(content_type).and_then(|label| Encoding::for_label(label.as_bytes())) {
+        return encoding;
+    }
+    let essence = mime::essence(content_type);
+    if essence == "text/html" || essence.is_empty() {
+        if let Some(encoding) = prescan(body) {
+            return encoding;
+        }
+    }
+    if str::from_utf8(body).is_ok() {
+        return UTF_8;
+    }
+    return WINDOWS_1252;
+}
+
+// Decodes a response body to UTF-8, replacing invalid sequences with U+FFFD
+pub fn decode(content_type: &str, body: &[u8]) -> String {
+    let (text, _, _) = detect(content_type, body).decode(body);
+    return text.into_owned();
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{SHIFT_JIS, UTF_16BE, UTF_16LE, WINDOWS_1251};
+
+    use super::*;
+
+    #[test]
+    fn byte_order_marks_win() {
+        let body = b"\xef\xbb\xbf<meta charset=\"windows-1251\">";
+        assert_eq!(detect("text/html; charset=shift_jis", body), UTF_8);
+        assert_eq!(detect("text/html; charset=utf-8", b"\xfe\xff\x00h"), UTF_16BE);
+        assert_eq!(detect("", b"\xff\xfeh\x00"), UTF_16LE);
+        // and the mark is not part of the text
+        assert_eq!(decode("text/plain", b"\xef\xbb\xbfhi"), "hi");
+    }
+
+    #[test]
+    fn header_then_meta() {
+        let meta = b"<html><head><meta charset=\"windows-1251\"></head>";
+        assert_eq!(detect("text/html; charset=\"Shift_JIS\"", meta), SHIFT_JIS);
+        assert_eq!(detect("text/html", meta), WINDOWS_1251);
+        // only HTML is prescanned
+        assert_eq!(detect("text/plain", meta), UTF_8);
+        let http_equiv = b"<!-- <meta charset=utf-8> -->\
+            <META HTTP-EQUIV=Content-Type CONTENT='text/html; charset=sjis'>";
+        assert_eq!(detect("", http_equiv), SHIFT_JIS);
+        // a content attribute counts only with http-equiv, and UTF-16 labels mean UTF-8
+        assert_eq!(detect("text/html", b"<meta content=\"text/html; charset=koi8-r\">"), UTF_8);
+        assert_eq!(detect("text/html", b"<meta charset=utf-16le>"), UTF_8);
+        // and an unknown label is passed over
+        assert_eq!(detect("text/html; charset=no-such-thing", meta), WINDOWS_1251);
+    }
+
+    #[test]
+    fn unlabelled_bodies() {
+        assert_eq!(detect("text/plain", "café".as_bytes()), UTF_8);
+        assert_eq!(detect("text/plain", b"caf\xe9"), WINDOWS_1252);
+        assert_eq!(decode("text/plain", b"caf\xe9"), "café");
+        assert_eq!(decode("text/html; charset=utf-8", b"caf\xe9"), "caf\u{fffd}");
+    }
+
+    #[test]
+    fn charset_params() {
+        assert_eq!(charset_param("text/html; Charset = 'latin1' ; q=1"), Some("latin1"));
+        assert_eq!(charset_param("text/html;charset=utf-8;format=flowed"), Some("utf-8"));
+        assert_eq!(charset_param("text/html; charset"), None);
+        assert_eq!(charset_param("text/html; charset=\"\""), None);
+    }
+}
@@ -0,0 +1,104 @@
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::io::{BufRead, BufReader, Read};
+
+use super::RequestError;
+
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+fn is_zlib_header(bytes: &[u8]) -> bool {
+    return bytes.len() >= 2
+        && bytes[0] & 0x0f == 8
+        && ((bytes[0] as u16) << 8 | bytes[1] as u16).is_multiple_of(31);
+}
+
+fn single_decoder<'a>(reader: Box<dyn Read + 'a>, coding: &str) -> Result<Box<dyn Read + 'a>, RequestError> {
+    match coding {
+        "identity" | "" => { return Ok(reader); }
+        "gzip" | "x-gzip" => { return Ok(Box::new(GzDecoder::new(reader))); }
+        "deflate" => {
+            // "deflate" is meant to be zlib wrapped, but plenty of servers
+            // send a raw deflate stream instead
+            let mut reader = BufReader::new(reader);
+            let zlib = is_zlib_header(reader.fill_buf().map_err(|e| RequestError::Decode(e.to_string()))?);
+            if zlib {
+                return Ok(Box::new(ZlibDecoder::new(reader)));
+            }
+            return Ok(Box::new(DeflateDecoder::new(reader)));
+        }
+        "br" => { return Ok(Box::new(brotli_decompressor::Decompressor::new(reader, 4096))); }
+        other => { return Err(RequestError::Decode(format!("unsupported content-encoding {}", other))); }
+    }
+}
+
+// Wraps a reader so it yields the body with every listed coding undone.
+// Codings are listed in the order they were applied, so undo them in reverse.
+pub fn decoder<'a>(reader: Box<dyn Read + 'a>, encoding: &str) -> Result<Box<dyn Read + 'a>, RequestError> {
+    let mut reader = reader;
+    for coding in encoding.rsplit(',') {
+        reader = single_decoder(reader, coding.trim().to_lowercase().as_str())?;
+    }
+    return Ok(reader);
+}
+
+pub fn decode(body: &[u8], encoding: &str) -> Result<Vec<u8>, RequestError> {
+    let mut decoded = Vec::new();
+    decoder(Box::new(body), encoding)?
+        .read_to_end(&mut decoded)
+        .map_err(|e| RequestError::Decode(format!("corrupt {} stream: {}", encoding, e)))?;
+    return Ok(decoded);
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    use super::*;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        return encoder.finish().unwrap();
+    }
+
+    // "hello brotli" as one uncompressed meta-block, then an empty last one
+    const BROTLI: &[u8] = b"\xb0\x00\x10hello brotli\x03";
+
+    #[test]
+    fn decodes() {
+        assert_eq!(decode(&gzip(b"hello gzip"), "gzip").unwrap(), b"hello gzip");
+        assert_eq!(decode(&gzip(b"hello gzip"), "X-GZIP").unwrap(), b"hello gzip");
+        assert_eq!(decode(BROTLI, "br").unwrap(), b"hello brotli");
+        assert_eq!(decode(b"as is", "identity").unwrap(), b"as is");
+        assert!(decode(b"", "gzip").unwrap_err().to_string().contains("corrupt gzip stream"));
+    }
+
+    #[test]
+    fn deflate_with_and_without_zlib() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"wrapped").unwrap();
+        assert_eq!(decode(&zlib.finish().unwrap(), "deflate").unwrap(), b"wrapped");
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(b"raw").unwrap();
+        assert_eq!(decode(&raw.finish().unwrap(), "deflate").unwrap(), b"raw");
+    }
+
+    #[test]
+    fn stacked_codings() {
+        // gzip was applied first, so it is undone last
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&gzip(b"twice")).unwrap();
+        assert_eq!(decode(&zlib.finish().unwrap(), "gzip, deflate").unwrap(), b"twice");
+        assert!(matches!(decode(b"x", "gzip, compress"), Err(RequestError::Decode(_))));
+    }
+
+    #[test]
+    fn truncated() {
+        let gzipped = gzip(b"a body long enough to be cut short somewhere in the middle");
+        assert!(matches!(decode(&gzipped[..gzipped.len() / 2], "gzip"), Err(RequestError::Decode(_))));
+        // a whole deflate stream without the gzip trailer is still cut short
+        assert!(matches!(decode(&gzipped[..gzipped.len() - 4], "gzip"), Err(RequestError::Decode(_))));
+        assert!(matches!(decode(&BROTLI[..8], "br"), Err(RequestError::Decode(_))));
+    }
+}
@@ -0,0 +1,512 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use super::date::{format_timestamp, now, parse_http_date};
+use super::{HeaderMap, Url};
+use crate::html::escape;
+use crate::profile;
+
+const MAX_PER_DOMAIN: usize = 50;
+const MAX_COOKIES: usize = 3000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    // set without a Domain attribute, so only sent back to that exact host
+    pub host_only: bool,
+    pub path: String,
+    // None for session cookies, which are never written to disk
+    pub expires: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: String,
+    created: i64,
+    last_access: i64,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: i64) -> bool {
+        return self.expires.is_some_and(|expires| expires <= now);
+    }
+
+    fn same_entry(&self, other: &Cookie) -> bool {
+        return self.name == other.name && self.domain == other.domain && self.path == other.path;
+    }
+}
+
+fn is_ip(host: &str) -> bool {
+    return host.starts_with('[') || host.parse::<IpAddr>().is_ok();
+}
+
+// Where a request comes from, which decides the SameSite cookies it is sent
+// with (RFC 6265bis section 5.2)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Initiator {
+    // typed in, a bookmark or anything else the user asked for directly
+    #[default]
+    User,
+    // a link followed or a form submitted on the page at this url
+    Navigation(Url),
+    // an image or the like loaded by the page at this url
+    Subresource(Url),
+}
+
+// The registrable domain of a host. Without a public suffix list the last
+// two labels stand in for it.
+fn site(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_lowercase();
+    if is_ip(&host) {
+        return host;
+    }
+    let labels: Vec<&str> = host.rsplitn(3, '.').take(2).collect();
+    return labels.into_iter().rev().collect::<Vec<&str>>().join(".");
+}
+
+// RFC 6265 section 5.1.3
+fn domain_match(host: &str, domain: &str) -> bool {
+    return host == domain || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.') && !is_ip(host));
+}
+
+// RFC 6265 section 5.1.4
+fn default_path(url: &Url) -> String {
+    match url.path.rfind('/') {
+        Some(0) | None => return "/".to_string(),
+        Some(slash) => return url.path[..slash].to_string(),
+    }
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    if request_path == cookie_path {
+        return true;
+    }
+    return request_path.starts_with(cookie_path)
+        && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/'));
+}
+
+// Parses a Set-Cookie header received from `url` following RFC 6265 section
+// 5.2 and 5.3, returning None for cookies the user agent must ignore
+pub fn parse_set_cookie(header: &str, url: &Url) -> Option<Cookie> {
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let (name, value) = (name.trim(), value.trim());
+    if name.is_empty() {
+        return None;
+    }
+
+    let host = url.hostname().to_lowercase();
+    let now = now();
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url),
+        expires: None,
+        secure: false,
+        http_only: false,
+        same_site: "Lax".to_string(),
+        created: now,
+        last_access: now,
+    };
+    let mut max_age = None;
+    for attribute in parts {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+        match key.trim().to_lowercase().as_str() {
+            "expires" => {
+                if let Some(expires) = parse_http_date(value) {
+                    cookie.expires = Some(expires);
+                }
+            }
+            "max-age" => {
+                if let Ok(seconds) = value.parse::<i64>() {
+                    max_age = Some(if seconds <= 0 { i64::MIN } else { now.saturating_add(seconds) });
+                }
+            }
+            "domain" => {
+                let domain = value.trim_start_matches('.').to_lowercase();
+                if !domain.is_empty() {
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "samesite" => {
+                cookie.same_site = match value.to_lowercase().as_str() {
+                    "strict" => "Strict",
+                    "none" => "None",
+                    _ => "Lax",
+                }
+                .to_string();
+            }
+            _ => {}
+        }
+    }
+    // Max-Age wins over Expires regardless of order
+    if max_age.is_some() {
+        cookie.expires = max_age;
+    }
+
+    if !cookie.host_only {
+        // without a public suffix list, at least refuse top level domains
+        // and any domain the request did not come from
+        if !domain_match(&host, &cookie.domain) || (!cookie.domain.contains('.') && cookie.domain != host) {
+            return None;
+        }
+    }
+    // only secure origins may set Secure cookies, and SameSite=None
+    // requires Secure (RFC 6265bis)
+    if cookie.secure && url.scheme != "https" {
+        return None;
+    }
+    if cookie.same_site == "None" && !cookie.secure {
+        return None;
+    }
+    return Some(cookie);
+}
+
+// Cookies for every site, persisted to a file in the profile directory.
+// Session cookies only live as long as the process.
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+    file: Option<PathBuf>,
+}
+
+impl CookieJar {
+    pub fn new(file: Option<PathBuf>) -> CookieJar {
+        let cookies = file.as_ref().and_then(|file| fs::read_to_string(file).ok()).map(|text| load(&text));
+        return CookieJar { cookies: Mutex::new(cookies.unwrap_or_default()), file };
+    }
+
+    pub fn set(&self, cookie: Cookie) {
+        let now = now();
+        let mut cookies = self.cookies.lock().unwrap();
+        let mut cookie = cookie;
+        if let Some(old) = cookies.iter().position(|old| old.same_entry(&cookie)) {
+            cookie.created = cookies[old].created;
+            cookies.remove(old);
+        }
+        cookies.retain(|cookie| !cookie.is_expired(now));
+        if !cookie.is_expired(now) {
+            cookies.push(cookie);
+        }
+        evict(&mut cookies);
+        self.save(&cookies);
+    }
+
+    // Stores every Set-Cookie in a response received from `url`
+    pub fn store_response(&self, url: &Url, headers: &HeaderMap) {
+        // cookies the user agent must ignore are dropped quietly
+        for cookie in headers.get_all("set-cookie").filter_map(|header| parse_set_cookie(header, url)) {
+            self.set(cookie);
+        }
+    }
+
+    // The Cookie header to send with a `method` request to `url`, if any
+    // apply. SameSite cookies stay off requests another site started, except
+    // that Lax ones go along when it navigates to `url` with a safe method.
+    pub fn header_for(&self, url: &Url, method: &str, initiator: &Initiator) -> Option<String> {
+        let host = url.hostname().to_lowercase();
+        let secure = url.scheme == "https";
+        let cross_site = match initiator {
+            Initiator::User => false,
+            Initiator::Navigation(from) | Initiator::Subresource(from) => site(from.hostname()) != site(&host),
+        };
+        let lax = matches!(initiator, Initiator::Navigation(_)) && matches!(method, "GET" | "HEAD");
+        let now = now();
+        let mut cookies = self.cookies.lock().unwrap();
+        let mut matching: Vec<&mut Cookie> = cookies
+            .iter_mut()
+            .filter(|cookie| !cookie.is_expired(now))
+            .filter(|cookie| if cookie.host_only { host == cookie.domain } else { domain_match(&host, &cookie.domain) })
+            .filter(|cookie| path_match(&url.path, &cookie.path))
+            .filter(|cookie| secure || !cookie.secure)
+            .filter(|cookie| !cross_site || cookie.same_site == "None" || (cookie.same_site == "Lax" && lax))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        // longer paths first, then older cookies first
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.created.cmp(&b.created)));
+        let mut pairs = Vec::new();
+        for cookie in matching {
+            cookie.last_access = now;
+            pairs.push(format!("{}={}", cookie.name, cookie.value));
+        }
+        return Some(pairs.join("; "));
+    }
+
+    pub fn all(&self) -> Vec<Cookie> {
+        let now = now();
+        let mut cookies: Vec<Cookie> = self
+            .cookies
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now))
+            .cloned()
+            .collect();
+        cookies.sort_by(|a, b| a.domain.cmp(&b.domain).then(a.name.cmp(&b.name)));
+        return cookies;
+    }
+
+    pub fn clear(&self) {
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.clear();
+        self.save(&cookies);
+    }
+
+    fn save(&self, cookies: &[Cookie]) {
+        if let Some(file) = &self.file {
+            if let Err(e) = fs::write(file, dump(cookies)) {
+                println!("Failed to save cookies to {}: {}", file.display(), e);
+            }
+        }
+    }
+}
+
+// Drops the least recently used cookies past the per domain and total limits
+fn evict(cookies: &mut Vec<Cookie>) {
+    cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.last_access));
+    let mut per_domain: Vec<(String, usize)> = Vec::new();
+    let mut kept = 0;
+    cookies.retain(|cookie| {
+        let count = match per_domain.iter_mut().find(|(domain, _)| *domain == cookie.domain) {
+            Some((_, count)) => count,
+            None => {
+                per_domain.push((cookie.domain.clone(), 0));
+                &mut per_domain.last_mut().unwrap().1
+            }
+        };
+        *count += 1;
+        kept += 1;
+        return *count <= MAX_PER_DOMAIN && kept <= MAX_COOKIES;
+    });
+}
+
+// One persistent cookie per line, tab separated in the order of the Cookie
+// fields, in the spirit of the Netscape cookies.txt format
+fn dump(cookies: &[Cookie]) -> String {
+    let mut text = String::new();
+    for cookie in cookies {
+        let expires = match cookie.expires {
+            Some(expires) => expires,
+            None => continue,
+        };
+        text.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            cookie.name,
+            cookie.value,
+            cookie.domain,
+            cookie.host_only,
+            cookie.path,
+            expires,
+            cookie.secure,
+            cookie.http_only,
+            cookie.same_site,
+            cookie.created,
+            cookie.last_access
+        ));
+    }
+    return text;
+}
+
+fn load(text: &str) -> Vec<Cookie> {
+    let now = now();
+    return text
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 11 {
+                return None;
+            }
+            return Some(Cookie {
+                name: fields[0].to_string(),
+                value: fields[1].to_string(),
+                domain: fields[2].to_string(),
+                host_only: fields[3] == "true",
+                path: fields[4].to_string(),
+                expires: Some(fields[5].parse().ok()?),
+                secure: fields[6] == "true",
+                http_only: fields[7] == "true",
+                same_site: fields[8].to_string(),
+                created: fields[9].parse().ok()?,
+                last_access: fields[10].parse().ok()?,
+            });
+        })
+        .filter(|cookie| !cookie.is_expired(now))
+        .collect();
+}
+
+// The jar shared by every request, stored in the profile directory
+pub fn shared() -> &'static CookieJar {
+    static JAR: OnceLock<CookieJar> = OnceLock::new();
+    return JAR.get_or_init(|| CookieJar::new(profile::path("cookies.txt")));
+}
+
+// The about:cookies page, one line per cookie grouped by domain
+pub fn page(jar: &CookieJar) -> String {
+    let cookies = jar.all();
+    let mut html = String::from("<html><body><h1>Cookies</h1>");
+    if cookies.is_empty() {
+        html.push_str("<p>No cookies are stored.</p></body></html>");
+        return html;
+    }
+    html.push_str("<pre>");
+    let mut domain = None;
+    for cookie in &cookies {
+        if domain != Some(&cookie.domain) {
+            let prefix = if cookie.host_only { "" } else { "." };
+            html.push_str(&format!("\n<b>{}{}</b>\n", prefix, escape(&cookie.domain)));
+            domain = Some(&cookie.domain);
+        }
+        let expires = cookie.expires.map(format_timestamp).unwrap_or_else(|| "session".to_string());
+        let mut flags = vec![format!("SameSite={}", cookie.same_site)];
+        if cookie.secure {
+            flags.push("Secure".to_string());
+        }
+        if cookie.http_only {
+            flags.push("HttpOnly".to_string());
+        }
+        html.push_str(&format!(
+            "  {}={}  path={}  expires={}  {}\n",
+            escape(&cookie.name),
+            escape(&cookie.value),
+            escape(&cookie.path),
+            expires,
+            flags.join(" ")
+        ));
+    }
+    html.push_str("</pre></body></html>");
+    return html;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        return Url::parse(url).unwrap();
+    }
+
+    #[test]
+    fn parses_defaults() {
+        let cookie = parse_set_cookie("id = a3fWa ", &url("http://www.example.com/docs/page")).unwrap();
+        assert_eq!((cookie.name.as_str(), cookie.value.as_str()), ("id", "a3fWa"));
+        assert_eq!((cookie.domain.as_str(), cookie.host_only), ("www.example.com", true));
+        assert_eq!(cookie.path, "/docs");
+        assert_eq!((cookie.expires, cookie.secure, cookie.http_only), (None, false, false));
+        assert_eq!(cookie.same_site, "Lax");
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let header = "id=1; Domain=.Example.com; Path=/app; Secure; HttpOnly; SameSite=Strict; Max-Age=60; Expires=Wed, 21 Oct 2015 07:28:00 GMT";
+        let cookie = parse_set_cookie(header, &url("https://www.example.com/")).unwrap();
+        assert_eq!((cookie.domain.as_str(), cookie.host_only), ("example.com", false));
+        assert_eq!(cookie.path, "/app");
+        assert!(cookie.secure && cookie.http_only);
+        assert_eq!(cookie.same_site, "Strict");
+        // Max-Age wins over Expires, even though it came first
+        assert!(cookie.expires.unwrap() > now());
+
+        let expired = parse_set_cookie("id=1; Max-Age=0", &url("http://example.com/")).unwrap();
+        assert!(expired.is_expired(now()));
+        let dated = parse_set_cookie("id=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT", &url("http://example.com/")).unwrap();
+        assert_eq!(dated.expires, Some(1445412480));
+        // a path that is not absolute is ignored
+        let relative = parse_set_cookie("id=1; Path=app", &url("http://example.com/a/b")).unwrap();
+        assert_eq!(relative.path, "/a");
+    }
+
+    #[test]
+    fn rejects() {
+        let http = url("http://www.example.com/");
+        for header in ["=value", "no equals sign", "id=1; Domain=other.com", "id=1; Domain=com", "id=1; Secure", "id=1; SameSite=None"] {
+            assert_eq!(parse_set_cookie(header, &http), None, "{}", header);
+        }
+        // SameSite=None is fine with Secure over https
+        assert!(parse_set_cookie("id=1; Secure; SameSite=None", &url("https://www.example.com/")).is_some());
+    }
+
+    #[test]
+    fn domains() {
+        assert!(domain_match("example.com", "example.com"));
+        assert!(domain_match("www.example.com", "example.com"));
+        assert!(!domain_match("badexample.com", "example.com"));
+        assert!(!domain_match("example.com", "www.example.com"));
+        assert!(!domain_match("10.0.0.1", "0.0.1"));
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(default_path(&url("http://example.com")), "/");
+        assert_eq!(default_path(&url("http://example.com/page")), "/");
+        assert_eq!(default_path(&url("http://example.com/docs/")), "/docs");
+        assert_eq!(default_path(&url("http://example.com/docs/web/page")), "/docs/web");
+
+        assert!(path_match("/docs", "/docs"));
+        assert!(path_match("/docs/web", "/docs"));
+        assert!(path_match("/docs/web", "/docs/"));
+        assert!(path_match("/anything", "/"));
+        assert!(!path_match("/docsets", "/docs"));
+        assert!(!path_match("/", "/docs"));
+    }
+
+    #[test]
+    fn header() {
+        let jar = CookieJar::new(None);
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "site=1; Domain=example.com");
+        headers.append("Set-Cookie", "host=2");
+        headers.append("Set-Cookie", "deep=3; Path=/docs");
+        headers.append("Set-Cookie", "secret=4; Secure");
+        jar.store_response(&url("https://example.com/"), &headers);
+        let header = |address: &str| jar.header_for(&url(address), "GET", &Initiator::User);
+
+        // longer paths come first, then older cookies
+        assert_eq!(header("https://example.com/docs/page").as_deref(), Some("deep=3; site=1; host=2; secret=4"));
+        // Secure cookies stay off plain http
+        assert_eq!(header("http://example.com/").as_deref(), Some("site=1; host=2"));
+        // host only cookies stay off subdomains
+        assert_eq!(header("http://www.example.com/").as_deref(), Some("site=1"));
+        assert_eq!(header("http://example.org/"), None);
+
+        // a cookie set again with Max-Age=0 is removed
+        let mut expire = HeaderMap::new();
+        expire.append("Set-Cookie", "host=2; Max-Age=0");
+        jar.store_response(&url("https://example.com/"), &expire);
+        assert_eq!(header("http://example.com/").as_deref(), Some("site=1"));
+    }
+
+    #[test]
+    fn same_site() {
+        let jar = CookieJar::new(None);
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "strict=1; SameSite=Strict");
+        headers.append("Set-Cookie", "lax=2; SameSite=Lax");
+        headers.append("Set-Cookie", "none=3; SameSite=None; Secure");
+        headers.append("Set-Cookie", "default=4");
+        jar.store_response(&url("https://www.example.com/"), &headers);
+        let page = url("https://www.example.com/");
+        let cookies = |initiator: Initiator, method: &str| {
+            return jar.header_for(&page, method, &initiator).unwrap_or_default();
+        };
+
+        assert_eq!(cookies(Initiator::User, "POST"), "strict=1; lax=2; none=3; default=4");
+        // another host on the same site is no stranger
+        let sibling = url("https://static.example.com/page");
+        assert_eq!(cookies(Initiator::Subresource(sibling), "GET"), "strict=1; lax=2; none=3; default=4");
+
+        let other = url("https://other.org/");
+        assert_eq!(cookies(Initiator::Navigation(other.clone()), "GET"), "lax=2; none=3; default=4");
+        assert_eq!(cookies(Initiator::Navigation(other.clone()), "POST"), "none=3");
+        assert_eq!(cookies(Initiator::Subresource(other), "GET"), "none=3");
+    }
+}
@@ -0,0 +1,103 @@
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+
+use super::{url, HeaderMap, RequestError, Response, Url};
+
+// Forgiving base64 as the fetch spec describes it: padding is optional
+const FORGIVING: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+fn strip_base64(media_type: &str) -> Option<&str> {
+    let (rest, last) = media_type.rsplit_once(';')?;
+    if last.trim().eq_ignore_ascii_case("base64") {
+        return Some(rest);
+    }
+    return None;
+}
+
+// Parses data:[<mediatype>][;base64],<data> per RFC 2397 and the fetch
+// spec's data: URL processor
+pub fn data(url: &Url) -> Result<Response, RequestError> {
+    let mut without_fragment = url.clone();
+    without_fragment.fragment = None;
+    let content = without_fragment.without_scheme();
+
+    let (media_type, payload) = content
+        .split_once(',')
+        .ok_or_else(|| RequestError::BadDataUrl("missing ','".to_string()))?;
+    let media_type = media_type.trim();
+    let (media_type, is_base64) = match strip_base64(media_type) {
+        Some(media_type) => (media_type.trim(), true),
+        None => (media_type, false),
+    };
+
+    let mut body = url::percent_decode(payload);
+    if is_base64 {
+        let cleaned: Vec<u8> = body.into_iter().filter(|b| !b.is_ascii_whitespace()).collect();
+        body = FORGIVING
+            .decode(&cleaned)
+            .map_err(|e| RequestError::BadDataUrl(format!("invalid base64: {}", e)))?;
+    }
+
+    let media_type = url::percent_decode_str(media_type);
+    let content_type = if media_type.is_empty() {
+        "text/plain;charset=US-ASCII".to_string()
+    } else if media_type.starts_with(';') {
+        format!("text/plain{}", media_type)
+    } else {
+        media_type
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", &content_type);
+    headers.insert("Content-Length", &body.len().to_string());
+    return Ok(Response::ok(url, headers, body));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(url: &str) -> Result<Response, RequestError> {
+        return data(&Url::parse(url).unwrap());
+    }
+
+    fn content_type(response: &Response) -> &str {
+        return response.headers.get("content-type").unwrap();
+    }
+
+    #[test]
+    fn plain() {
+        let response = get("data:,Hello%2C%20World%21#greeting").unwrap();
+        assert_eq!(response.body, b"Hello, World!");
+        assert_eq!(content_type(&response), "text/plain;charset=US-ASCII");
+        assert_eq!(response.headers.get("content-length"), Some("13"));
+
+        let response = get("data:text/html,%3Ch1%3Ehi%3C%2Fh1%3E").unwrap();
+        assert_eq!((content_type(&response), response.body.as_slice()), ("text/html", &b"<h1>hi</h1>"[..]));
+        // parameters alone still mean text/plain
+        let response = get("data:;charset=utf-8,%E2%9C%93").unwrap();
+        assert_eq!((content_type(&response), response.body.as_slice()), ("text/plain;charset=utf-8", "✓".as_bytes()));
+    }
+
+    #[test]
+    fn base64() {
+        let response = get("data:text/plain;base64,SGVsbG8sIFdvcmxkIQ==").unwrap();
+        assert_eq!((content_type(&response), response.body.as_slice()), ("text/plain", &b"Hello, World!"[..]));
+        // padding is optional, and whitespace and percent-encoding are allowed
+        assert_eq!(get("data:;BASE64,SGVs%20bG8").unwrap().body, b"Hello");
+        let png = get("data:image/png;base64,iVBORw0KGgo=").unwrap();
+        assert_eq!((content_type(&png), png.body.as_slice()), ("image/png", &b"\x89PNG\r\n\x1a\n"[..]));
+        // "base64" has to be the last parameter
+        assert_eq!(get("data:text/plain;base64;charset=utf-8,SGk").unwrap().body, b"SGk");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!(get("data:text/plain"), Err(RequestError::BadDataUrl(_))));
+        assert!(matches!(get("data:;base64,not*base64"), Err(RequestError::BadDataUrl(_))));
+    }
+}
@@ -0,0 +1,683 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::{env, thread};
+
+use super::about;
+use super::url::percent_decode;
+use super::{default_transport, mime, Cancel, HeaderMap, Request, RequestError, Response, Sink, Stores, Transport, Url};
+use crate::html::escape;
+use crate::profile;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    Active,
+    Paused,
+    Done,
+    Failed(String),
+}
+
+#[derive(Clone)]
+pub struct Download {
+    pub id: u64,
+    pub url: Url,
+    // chosen once the response names the file, until then None
+    pub file: Option<PathBuf>,
+    pub received: u64,
+    pub total: Option<u64>,
+    pub state: State,
+    request: Request,
+    // a name picked by the page, from <a download="name">
+    name: Option<String>,
+    // ETag or Last-Modified, sent as If-Range so a resumed download
+    // restarts if the file changed in between
+    validator: Option<String>,
+    resumable: bool,
+    cancel: Cancel,
+}
+
+impl Download {
+    // Bytes go to a .part file until the download completes
+    fn part_file(&self) -> Option<PathBuf> {
+        return self.file.as_ref().map(|file| part_path(file));
+    }
+
+    // Whether the request can be sent again to resume or retry, which only
+    // holds for GETs
+    pub fn repeatable(&self) -> bool {
+        return self.request.method == "GET";
+    }
+}
+
+fn part_path(file: &Path) -> PathBuf {
+    let mut part = file.as_os_str().to_owned();
+    part.push(".part");
+    return PathBuf::from(part);
+}
+
+// Types the browser can show itself rather than save
+pub fn renderable(mime: &str) -> bool {
+    return mime.starts_with("text/") || mime.starts_with("image/");
+}
+
+// Whether Content-Disposition asks for the body to be saved, and the file
+// name it suggests. filename* (RFC 6266, RFC 8187) wins over filename.
+fn disposition(headers: &HeaderMap) -> (bool, Option<String>) {
+    let value = match headers.get("content-disposition") {
+        Some(value) => value,
+        None => { return (false, None); }
+    };
+    let mut params = value.split(';');
+    let attachment = params.next().is_some_and(|kind| kind.trim().eq_ignore_ascii_case("attachment"));
+    let (mut plain, mut extended) = (None, None);
+    for param in params {
+        let (name, value) = match param.split_once('=') {
+            Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+        match name.as_str() {
+            "filename" => plain = Some(value.trim_matches('"').to_string()),
+            "filename*" => {
+                // charset'language'percent-encoded-value
+                if let Some((charset, rest)) = value.split_once('\'') {
+                    let encoded = rest.split_once('\'').map(|(_, encoded)| encoded).unwrap_or(rest);
+                    let bytes = percent_decode(encoded);
+                    if charset.eq_ignore_ascii_case("utf-8") {
+                        extended = Some(String::from_utf8_lossy(&bytes).to_string());
+                    } else {
+                        extended = Some(bytes.iter().map(|b| *b as char).collect());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    return (attachment, extended.or(plain));
+}
+
+// Keeps just the last path component, so a server cannot write outside
+// the downloads directory
+fn sanitize(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("").trim();
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    return Some(name);
+}
+
+fn file_name(url: &Url, suggested: Option<&str>) -> String {
+    return suggested
+        .and_then(sanitize)
+        .or_else(|| sanitize(&String::from_utf8_lossy(&percent_decode(&url.path))))
+        .unwrap_or_else(|| "download".to_string());
+}
+
+// The first of name, "name (1)", "name (2)", ... not already taken
+fn unique_file(dir: &Path, name: &str, taken: &[PathBuf]) -> PathBuf {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot..]),
+        _ => (name, ""),
+    };
+    let mut n = 0;
+    loop {
+        let candidate = if n == 0 {
+            dir.join(name)
+        } else {
+            dir.join(format!("{} ({}){}", stem, n, extension))
+        };
+        if !candidate.exists() && !part_path(&candidate).exists() && !taken.contains(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+// Every download this session, saved to a directory that can be changed
+// with set_dir, $BOWSER_DOWNLOADS or about:settings
+pub struct Downloads {
+    dir: Mutex<PathBuf>,
+    list: Mutex<Vec<Download>>,
+}
+
+impl Downloads {
+    pub fn new(dir: PathBuf) -> Downloads {
+        return Downloads { dir: Mutex::new(dir), list: Mutex::new(Vec::new()) };
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        return self.dir.lock().unwrap().clone();
+    }
+
+    pub fn set_dir(&self, dir: PathBuf) {
+        *self.dir.lock().unwrap() = dir;
+    }
+
+    pub fn all(&self) -> Vec<Download> {
+        return self.list.lock().unwrap().clone();
+    }
+
+    pub fn get(&self, id: u64) -> Option<Download> {
+        return self.list.lock().unwrap().iter().find(|download| download.id == id).cloned();
+    }
+
+    fn update<F: FnOnce(&mut Download)>(&self, id: u64, f: F) {
+        if let Some(download) = self.list.lock().unwrap().iter_mut().find(|download| download.id == id) {
+            f(download);
+        }
+    }
+
+    // Adds a download of `request` without starting it
+    pub fn add(&self, request: Request, name: Option<String>) -> u64 {
+        let mut list = self.list.lock().unwrap();
+        let id = list.len() as u64;
+        list.push(Download {
+            id,
+            url: request.url.without_userinfo(),
+            file: None,
+            received: 0,
+            total: None,
+            state: State::Paused,
+            request,
+            name,
+            validator: None,
+            resumable: false,
+            cancel: Cancel::new(),
+        });
+        return id;
+    }
+
+    // Adds a download whose response is already arriving, for a sink to
+    // stream into. `cancel` is the one the request was sent with, so pausing
+    // the download stops it.
+    fn divert(&self, request: Request, cancel: &Cancel) -> u64 {
+        let id = self.add(request, None);
+        self.update(id, |download| {
+            download.state = State::Active;
+            download.cancel = cancel.clone();
+        });
+        return id;
+    }
+
+    // Adds a download and starts it on its own thread
+    pub fn start(&'static self, request: Request, name: Option<String>) -> u64 {
+        let id = self.add(request, name);
+        self.resume(id);
+        return id;
+    }
+
+    pub fn pause(&self, id: u64) {
+        self.update(id, |download| {
+            if download.state == State::Active {
+                download.state = State::Paused;
+                download.cancel.cancel();
+            }
+        });
+    }
+
+    fn activate(&self, id: u64) -> bool {
+        let mut activated = false;
+        self.update(id, |download| {
+            if matches!(download.state, State::Paused | State::Failed(_)) && download.repeatable() {
+                download.state = State::Active;
+                download.cancel = Cancel::new();
+                activated = true;
+            }
+        });
+        return activated;
+    }
+
+    // Restarts a paused or failed download on its own thread, continuing
+    // from where it stopped if the server supports ranges
+    pub fn resume(&'static self, id: u64) {
+        if self.activate(id) {
+            thread::spawn(move || self.run(&mut default_transport(), id));
+        }
+    }
+
+    // Like resume, but over `transport` and blocking until the download is
+    // done, paused or failed
+    pub fn resume_with<T: Transport>(&self, transport: &mut T, id: u64) {
+        if self.activate(id) {
+            self.run(transport, id);
+        }
+    }
+
+    // Stops a download and deletes what it saved so far
+    pub fn cancel(&self, id: u64) {
+        self.update(id, |download| {
+            download.cancel.cancel();
+            if download.state != State::Done {
+                if let Some(part) = download.part_file() {
+                    let _ = fs::remove_file(part);
+                }
+                download.state = State::Failed("Cancelled".to_string());
+                download.received = 0;
+            }
+        });
+    }
+
+    fn run<T: Transport>(&self, transport: &mut T, id: u64) {
+        let download = match self.get(id) {
+            Some(download) => download,
+            None => { return; }
+        };
+        let mut request = download.request.clone();
+        // ranges count bytes of the encoded body, so only ask for it as is
+        request.headers.insert("Accept-Encoding", "identity");
+        if download.received > 0 && download.resumable {
+            request.headers.insert("Range", &format!("bytes={}-", download.received));
+            if let Some(validator) = &download.validator {
+                request.headers.insert("If-Range", validator);
+            }
+        }
+
+        let sink = FileSink { downloads: self, id };
+        // the body never lands in memory, so there is nothing to cache
+        let stores = Stores { cache: None, cancel: Some(&download.cancel), sink: Some(&sink), ..Stores::shared() };
+        let result = request.send_with(transport, stores);
+        self.settle(id, &result);
+    }
+
+    // Records how the request streaming a download ended
+    pub fn settle(&self, id: u64, result: &Result<Response, RequestError>) {
+        let state = match result {
+            Ok(response) if matches!(response.status, 200 | 206 | 416) => self.finish(id),
+            Ok(response) => State::Failed(format!("{} {}", response.status, response.reason)),
+            Err(RequestError::Cancelled) => State::Paused,
+            Err(error) => State::Failed(error.to_string()),
+        };
+        self.update(id, |download| {
+            // a pause or cancel that arrived meanwhile wins
+            if download.state == State::Active {
+                download.state = state;
+            }
+            // nothing will stop a finished download, so let go of its sockets
+            if matches!(download.state, State::Done | State::Failed(_)) {
+                download.cancel = Cancel::new();
+            }
+        });
+    }
+
+    // Moves the .part file into place once every byte has arrived
+    fn finish(&self, id: u64) -> State {
+        let download = match self.get(id) {
+            Some(download) => download,
+            None => { return State::Failed("Forgotten".to_string()); }
+        };
+        if download.total.is_some_and(|total| total != download.received) {
+            return State::Failed(format!("Only received {} bytes", download.received));
+        }
+        if let (Some(file), Some(part)) = (&download.file, download.part_file()) {
+            if let Err(e) = fs::rename(&part, file) {
+                return State::Failed(e.to_string());
+            }
+        }
+        self.update(id, |download| download.total = Some(download.received));
+        return State::Done;
+    }
+
+    fn choose_file(&self, id: u64, url: &Url, headers: &HeaderMap) -> PathBuf {
+        let mut list = self.list.lock().unwrap();
+        let taken: Vec<PathBuf> = list.iter().filter_map(|download| download.file.clone()).collect();
+        let download = list.iter_mut().find(|download| download.id == id).unwrap();
+        if let Some(file) = &download.file {
+            return file.clone();
+        }
+        let (_, suggested) = disposition(headers);
+        let name = file_name(url, suggested.as_deref().or(download.name.as_deref()));
+        let file = unique_file(&self.dir.lock().unwrap(), &name, &taken);
+        download.file = Some(file.clone());
+        return file;
+    }
+}
+
+// Receives the body of a download, appending to the .part file when the
+// server sent the range that was asked for
+struct FileSink<'a> {
+    downloads: &'a Downloads,
+    id: u64,
+}
+
+impl<'a> FileSink<'a> {
+    fn writer(&self, url: &Url, status: u16, headers: &HeaderMap) -> Result<Option<Box<dyn Write + 'a>>, RequestError> {
+        if status != 200 && status != 206 {
+            return Ok(None);
+        }
+        let file = self.downloads.choose_file(self.id, url, headers);
+        fs::create_dir_all(self.downloads.dir())?;
+        let part = part_path(&file);
+        // the sink is given the decoded body, which a compressed length
+        // does not describe
+        let encoded = headers.get_joined("content-encoding").is_some_and(|encoding| !encoding.trim().eq_ignore_ascii_case("identity"));
+        let length = headers
+            .get("content-length")
+            .and_then(|length| length.trim().parse::<u64>().ok())
+            .filter(|_| !encoded);
+        let validator = headers
+            .get("etag")
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(headers.get("last-modified"))
+            .map(|validator| validator.to_string());
+        let resumable = headers.get("accept-ranges").is_some_and(|ranges| ranges.trim().eq_ignore_ascii_case("bytes"));
+
+        let mut append = false;
+        let mut error = None;
+        self.downloads.update(self.id, |download| {
+            if status == 206 {
+                // Content-Range: bytes start-end/total
+                let range = headers.get("content-range").unwrap_or("");
+                let start = range
+                    .trim()
+                    .strip_prefix("bytes ")
+                    .and_then(|range| range.split('-').next())
+                    .and_then(|start| start.trim().parse::<u64>().ok());
+                if start != Some(download.received) {
+                    error = Some(RequestError::BadHeader(format!("content-range: {}", range)));
+                    return;
+                }
+                download.total = range.rsplit('/').next().and_then(|total| total.trim().parse().ok());
+                append = true;
+            } else {
+                download.received = 0;
+                download.total = length;
+            }
+            download.resumable = resumable || status == 206;
+            if validator.is_some() {
+                download.validator = validator;
+            }
+        });
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        let out = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(part)?;
+        return Ok(Some(Box::new(Progress { downloads: self.downloads, id: self.id, out })));
+    }
+}
+
+impl Sink for FileSink<'_> {
+    fn open(
+        &self,
+        url: &Url,
+        status: u16,
+        headers: &HeaderMap,
+        _start: &[u8],
+    ) -> Result<Option<Box<dyn Write + '_>>, RequestError> {
+        return self.writer(url, status, headers);
+    }
+}
+
+struct Progress<'a> {
+    downloads: &'a Downloads,
+    id: u64,
+    out: File,
+}
+
+impl Write for Progress<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // a paused or cancelled download stops taking bytes, which ends the
+        // request streaming into it
+        let mut active = false;
+        self.downloads.update(self.id, |download| active = download.state == State::Active);
+        if !active {
+            return Err(io::Error::other("download stopped"));
+        }
+        let written = self.out.write(buf)?;
+        self.downloads.update(self.id, |download| download.received += written as u64);
+        return Ok(written);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.out.flush();
+    }
+}
+
+// Passed as the sink of a page load: a response the browser cannot show,
+// going by its type or its sniffed first bytes, is streamed into a new
+// download instead of being read into memory. The request is not sent
+// again, so POST results and one-time links are kept.
+pub struct Divert<'a> {
+    downloads: &'static Downloads,
+    request: &'a Request,
+    // the request's Cancel, which the download takes over
+    cancel: &'a Cancel,
+    diverted: Mutex<Option<u64>>,
+    on_divert: Option<Box<dyn Fn(u64) + Sync + 'a>>,
+}
+
+impl<'a> Divert<'a> {
+    pub fn new(downloads: &'static Downloads, request: &'a Request, cancel: &'a Cancel) -> Divert<'a> {
+        return Divert { downloads, request, cancel, diverted: Mutex::new(None), on_divert: None };
+    }
+
+    // Calls `f` with the download as soon as the response is diverted,
+    // while its body is still arriving
+    pub fn on_divert<F: Fn(u64) + Sync + 'a>(mut self, f: F) -> Divert<'a> {
+        self.on_divert = Some(Box::new(f));
+        return self;
+    }
+
+    // The download the response went to, if it was diverted. Once the
+    // request returns, pass its result to Downloads::settle.
+    pub fn diverted(&self) -> Option<u64> {
+        return *self.diverted.lock().unwrap();
+    }
+}
+
+impl Sink for Divert<'_> {
+    fn open(
+        &self,
+        url: &Url,
+        status: u16,
+        headers: &HeaderMap,
+        start: &[u8],
+    ) -> Result<Option<Box<dyn Write + '_>>, RequestError> {
+        if status != 200 {
+            return Ok(None);
+        }
+        let (attachment, _) = disposition(headers);
+        // the same type Response::mime_type will settle on
+        let no_sniff = headers
+            .get("x-content-type-options")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("nosniff"));
+        if !attachment && renderable(&mime::sniff(headers.get("content-type"), no_sniff, start)) {
+            return Ok(None);
+        }
+        let mut request = self.request.clone();
+        request.url = url.clone();
+        let id = self.downloads.divert(request, self.cancel);
+        *self.diverted.lock().unwrap() = Some(id);
+        let writer = FileSink { downloads: self.downloads, id }.writer(url, status, headers)?;
+        if let Some(on_divert) = &self.on_divert {
+            on_divert(id);
+        }
+        return Ok(writer);
+    }
+}
+
+// The downloads directory: $BOWSER_DOWNLOADS, else the one chosen on
+// about:settings, else ~/Downloads if there is one, else a directory in the
+// profile
+fn default_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("BOWSER_DOWNLOADS") {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = profile::setting("downloads") {
+        return PathBuf::from(dir);
+    }
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from);
+    if let Some(downloads) = home.map(|home| home.join("Downloads")).filter(|dir| dir.is_dir()) {
+        return downloads;
+    }
+    return profile::path("downloads").unwrap_or_else(env::temp_dir);
+}
+
+pub fn shared() -> &'static Downloads {
+    static DOWNLOADS: OnceLock<Downloads> = OnceLock::new();
+    return DOWNLOADS.get_or_init(|| Downloads::new(default_dir()));
+}
+
+fn size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    if bytes < 1024 * 1024 {
+        return format!("{:.1} KiB", bytes as f64 / 1024.0);
+    }
+    return format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+}
+
+// The about:downloads page. The query carries the buttons: ?pause=N,
+// ?resume=N and ?cancel=N act on a download before the list is drawn.
+pub fn page(downloads: &'static Downloads, query: Option<&str>) -> String {
+    for pair in query.unwrap_or("").split('&') {
+        let (action, id) = pair.split_once('=').unwrap_or((pair, ""));
+        let id = match id.parse::<u64>() {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        match action {
+            "pause" => downloads.pause(id),
+            "resume" => downloads.resume(id),
+            "cancel" => downloads.cancel(id),
+            _ => {}
+        }
+    }
+
+    let mut html = String::from("<html><body><h1>Downloads</h1>");
+    html.push_str(&format!("<p>Saving to {}</p>", escape(&downloads.dir().display().to_string())));
+    let list = downloads.all();
+    if list.is_empty() {
+        html.push_str("<p>Nothing has been downloaded.</p></body></html>");
+        return html;
+    }
+    for download in list.iter().rev() {
+        let name = match &download.file {
+            Some(file) => file.display().to_string(),
+            None => download.url.to_string(),
+        };
+        let progress = match download.total {
+            Some(total) if total > 0 => format!(
+                "{} of {} ({}%)",
+                size(download.received),
+                size(total),
+                download.received * 100 / total
+            ),
+            _ => size(download.received),
+        };
+        let (state, actions) = match &download.state {
+            State::Active => ("Downloading".to_string(), vec!["pause", "cancel"]),
+            State::Paused => ("Paused".to_string(), vec!["resume", "cancel"]),
+            State::Done => ("Done".to_string(), vec![]),
+            State::Failed(reason) => (format!("Failed: {}", reason), vec!["resume"]),
+        };
+        // what cannot be sent again can only be cancelled
+        let actions: Vec<&str> = actions.into_iter().filter(|action| download.repeatable() || *action == "cancel").collect();
+        html.push_str(&format!("<p><b>{}</b><br>{}<br>{} - {}", escape(&name), escape(&download.url.to_string()), state, progress));
+        for action in actions {
+            let href = about::action_url("downloads", &format!("{}={}", action, download.id));
+            html.push_str(&format!(" <a href=\"{}\">{}</a>", escape(&href), action));
+        }
+        html.push_str("</p>");
+    }
+    html.push_str("</body></html>");
+    return html;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MockTransport;
+    use super::*;
+
+    fn downloads(name: &str) -> &'static Downloads {
+        let dir = env::temp_dir().join(format!("bowser-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        return Box::leak(Box::new(Downloads::new(dir)));
+    }
+
+    #[test]
+    fn diverts_without_sending_again() {
+        let downloads = downloads("divert");
+        let mut transport = MockTransport::new();
+        transport.respond(
+            "example.com",
+            80,
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\nContent-Length: 7\r\n\r\nPK\x03\x04zip",
+        );
+        let url = Url::parse("http://example.com/submit").unwrap();
+        let request = Request::post(&url).body("application/x-www-form-urlencoded", b"once=1".to_vec());
+        let cancel = Cancel::new();
+        let divert = Divert::new(downloads, &request, &cancel);
+        let result = request.send_with(&mut transport, Stores { sink: Some(&divert), ..Stores::default() });
+        assert!(result.as_ref().is_ok_and(|response| response.streamed && response.body.is_empty()));
+
+        let id = divert.diverted().unwrap();
+        downloads.settle(id, &result);
+        let download = downloads.get(id).unwrap();
+        assert_eq!((download.state, download.received), (State::Done, 7));
+        assert_eq!(fs::read(download.file.unwrap()).unwrap(), b"PK\x03\x04zip");
+        // the POST went out once, and cannot be resumed
+        assert_eq!(transport.connections().len(), 1);
+        assert!(!downloads.get(id).unwrap().repeatable());
+        assert!(!downloads.activate(id));
+    }
+
+    #[test]
+    fn leaves_pages_alone() {
+        let downloads = downloads("pages");
+        let mut transport = MockTransport::new();
+        transport.respond("example.com", 80, b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 2\r\n\r\nhi");
+        let request = Request::get(&Url::parse("http://example.com/").unwrap());
+        let cancel = Cancel::new();
+        let divert = Divert::new(downloads, &request, &cancel);
+        let response = request.send_with(&mut transport, Stores { sink: Some(&divert), ..Stores::default() }).unwrap();
+        assert_eq!(response.body, b"hi");
+        assert_eq!(divert.diverted(), None);
+        assert!(downloads.all().is_empty());
+
+        // an untyped page is sniffed, and kept in memory all the same
+        transport.respond("example.com", 80, b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n<html>hi</a>x");
+        let response = request.send_with(&mut transport, Stores { sink: Some(&divert), ..Stores::default() }).unwrap();
+        assert!(!response.streamed);
+        assert_eq!((response.body.as_slice(), response.mime_type().as_str()), (&b"<html>hi</a>x"[..], "text/html"));
+        assert!(downloads.all().is_empty());
+    }
+
+    #[test]
+    fn diverts_sniffed_bodies() {
+        let downloads = downloads("sniffed");
+        let mut transport = MockTransport::new();
+        transport.respond("example.com", 80, b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n%PDF-1.7\n");
+        let request = Request::get(&Url::parse("http://example.com/paper").unwrap());
+        let cancel = Cancel::new();
+        let noticed = Mutex::new(None);
+        let divert = Divert::new(downloads, &request, &cancel).on_divert(|id| *noticed.lock().unwrap() = Some(id));
+        let result = request.send_with(&mut transport, Stores { sink: Some(&divert), ..Stores::default() });
+        assert!(result.as_ref().is_ok_and(|response| response.streamed && response.body.is_empty()));
+
+        let id = divert.diverted().unwrap();
+        assert_eq!(*noticed.lock().unwrap(), Some(id));
+        // the download took over the request's Cancel
+        downloads.get(id).unwrap().cancel.cancel();
+        assert!(cancel.is_cancelled());
+        downloads.settle(id, &result);
+        let download = downloads.get(id).unwrap();
+        assert_eq!(fs::read(download.file.unwrap()).unwrap(), b"%PDF-1.7\n");
+    }
+
+    #[test]
+    fn stops_when_paused() {
+        let downloads = downloads("pause");
+        let id = downloads.divert(Request::get(&Url::parse("http://example.com/big.bin").unwrap()), &Cancel::new());
+        let mut out = FileSink { downloads, id }
+            .writer(&downloads.get(id).unwrap().url, 200, &HeaderMap::new())
+            .unwrap()
+            .unwrap();
+        out.write_all(b"some").unwrap();
+        downloads.pause(id);
+        assert!(out.write_all(b"more").is_err());
+        assert_eq!(downloads.get(id).unwrap().received, 4);
+    }
+}
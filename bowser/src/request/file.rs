@@ -0,0 +1,161 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use super::{date, mime, url, HeaderMap, RequestError, Response, Url};
+use crate::html::escape;
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "K", "M", "G"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        return format!("{}{}", size, UNITS[unit]);
+    }
+    return format!("{:.1}{}", size, UNITS[unit]);
+}
+
+fn modified(metadata: &fs::Metadata) -> String {
+    return metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| date::format_timestamp(since.as_secs() as i64))
+        .unwrap_or_else(|| "-".to_string());
+}
+
+// An index page with one line per entry, directories first, laid out in
+// columns inside a <pre> like a classic server listing
+fn directory_listing(path: &Path, url: &Url) -> Result<String, RequestError> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        entries.push((metadata.is_dir(), name, metadata));
+    }
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.to_lowercase().cmp(&b.1.to_lowercase())));
+
+    let title = escape(&url::percent_decode_str(&url.path));
+    let mut html = format!("<html><body><h1>Index of {}</h1><pre>", title);
+    if path.parent().is_some() {
+        html.push_str("<a href=\"../\">../</a>\n");
+    }
+    for (is_dir, name, metadata) in entries {
+        let display = if is_dir { format!("{}/", name) } else { name.clone() };
+        let href = if is_dir { format!("{}/", url::percent_encode(&name)) } else { url::percent_encode(&name) };
+        let size = if is_dir { "-".to_string() } else { format_size(metadata.len()) };
+        let padding = " ".repeat(40usize.saturating_sub(display.chars().count()) + 1);
+        html.push_str(&format!(
+            "<a href=\"{}\">{}</a>{}{}  {:>8}\n",
+            href,
+            escape(&display),
+            padding,
+            modified(&metadata),
+            size
+        ));
+    }
+    html.push_str("</pre></body></html>");
+    return Ok(html);
+}
+
+pub fn request_file(url: &Url) -> Result<Response, RequestError> {
+    let decoded = url::percent_decode_str(&url.path);
+    let path = Path::new(&decoded);
+    let metadata = fs::metadata(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => RequestError::NotFound(decoded.clone()),
+        _ => RequestError::Io(e),
+    })?;
+
+    let mut headers = HeaderMap::new();
+    if metadata.is_dir() {
+        // relative links in the listing only resolve if the url names a directory
+        if !url.path.ends_with('/') {
+            let mut directory = url.clone();
+            directory.path.push('/');
+            return request_file(&directory);
+        }
+        let body = directory_listing(path, url)?.into_bytes();
+        headers.insert("Content-Type", "text/html; charset=utf-8");
+        return Ok(Response::ok(url, headers, body));
+    }
+
+    let mut body = Vec::new();
+    fs::File::open(path)?.read_to_end(&mut body)?;
+    headers.insert("Content-Type", mime::guess(path, &body));
+    headers.insert("Content-Length", &body.len().to_string());
+    return Ok(Response::ok(url, headers, body));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bowser-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    fn file_url(path: &Path) -> Url {
+        return Url::parse(&format!("file://{}", path.display())).unwrap();
+    }
+
+    #[test]
+    fn lists_directories() {
+        let dir = directory("listing");
+        fs::create_dir(dir.join("zoo")).unwrap();
+        fs::write(dir.join("b.txt"), "12345").unwrap();
+        fs::write(dir.join("A <b> & c.txt"), "").unwrap();
+
+        // the directory's url is given its slash so relative links work
+        let response = request_file(&file_url(&dir)).unwrap();
+        assert!(response.final_url.path.ends_with('/'));
+        assert_eq!(response.headers.get("content-type"), Some("text/html; charset=utf-8"));
+        let html = String::from_utf8(response.body).unwrap();
+        let lines: Vec<&str> = html.lines().collect();
+        assert!(lines[0].ends_with("<a href=\"../\">../</a>"), "{}", html);
+        // directories first, then files regardless of case, with names
+        // escaped for the page and encoded for the link
+        assert!(lines[1].starts_with("<a href=\"zoo/\">zoo/</a> "), "{}", html);
+        assert!(lines[1].ends_with(" -"), "{}", html);
+        assert!(lines[2].starts_with("<a href=\"A%20%3Cb%3E%20%26%20c.txt\">A &lt;b&gt; &amp; c.txt</a> "), "{}", html);
+        assert!(lines[3].starts_with("<a href=\"b.txt\">b.txt</a> ") && lines[3].ends_with(" 5B"), "{}", html);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reads_files() {
+        let dir = directory("files");
+        fs::write(dir.join("picture"), b"\x89PNG\r\n\x1a\nrest").unwrap();
+        fs::write(dir.join("notes.md"), "# notes").unwrap();
+
+        let response = request_file(&file_url(&dir.join("picture"))).unwrap();
+        assert_eq!(response.headers.get("content-type"), Some("image/png"));
+        assert_eq!(response.headers.get("content-length"), Some("12"));
+        let response = request_file(&file_url(&dir.join("notes.md"))).unwrap();
+        assert_eq!(response.body, b"# notes");
+        assert!(matches!(request_file(&file_url(&dir.join("missing"))), Err(RequestError::NotFound(_))));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0B");
+        assert_eq!(format_size(1023), "1023B");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024 * 1024), "5120.0G");
+    }
+}
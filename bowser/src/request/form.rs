@@ -0,0 +1,256 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::url::{percent_decode, percent_encode};
+use super::{mime, Request, RequestError, Url};
+
+#[derive(Debug, Clone)]
+pub enum FormValue {
+    Text(String),
+    // a file input, None when no file was chosen
+    File(Option<PathBuf>),
+}
+
+// application/x-www-form-urlencoded byte serializer from the WHATWG URL spec
+fn form_encode(input: &str) -> String {
+    let mut encoded = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => encoded.push(byte as char),
+            b' ' => encoded.push('+'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    return encoded;
+}
+
+// application/x-www-form-urlencoded parser from the WHATWG URL spec, the
+// inverse of urlencode
+pub fn urldecode(query: &str) -> Vec<(String, String)> {
+    let decode = |text: &str| String::from_utf8_lossy(&percent_decode(&text.replace('+', " "))).to_string();
+    return query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            return (decode(name), decode(value));
+        })
+        .collect();
+}
+
+fn file_name(path: &Option<PathBuf>) -> String {
+    return path
+        .as_ref()
+        .and_then(|path| path.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+}
+
+// Files are sent by name only, since their contents cannot be urlencoded.
+// A first entry named "isindex" contributes just its value, which is how a
+// bare search string ends up as the whole query.
+pub fn urlencode(entries: &[(String, FormValue)]) -> String {
+    let mut pairs = Vec::new();
+    for (i, (name, value)) in entries.iter().enumerate() {
+        let value = match value {
+            FormValue::Text(text) => text.clone(),
+            FormValue::File(path) => file_name(path),
+        };
+        if i == 0 && name == "isindex" {
+            pairs.push(form_encode(&value));
+        } else {
+            pairs.push(format!("{}={}", form_encode(name), form_encode(&value)));
+        }
+    }
+    return pairs.join("&");
+}
+
+fn new_boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.subsec_nanos()).unwrap_or(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    return format!("----BowserFormBoundary{:08x}{:08x}", nanos, count);
+}
+
+// Quotes and line breaks would end the header early, so they are percent
+// encoded as the HTML spec asks
+fn escape_name(name: &str) -> String {
+    return name.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A");
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    return haystack.windows(needle.len()).any(|window| window == needle);
+}
+
+// Encodes the entries as multipart/form-data, reading chosen files from
+// disk, and returns the content type with its boundary alongside the body
+pub fn multipart(entries: &[(String, FormValue)]) -> Result<(String, Vec<u8>), RequestError> {
+    let mut parts = Vec::new();
+    for (name, value) in entries {
+        let mut part = format!("Content-Disposition: form-data; name=\"{}\"", escape_name(name)).into_bytes();
+        match value {
+            FormValue::Text(text) => {
+                part.extend_from_slice(b"\r\n\r\n");
+                part.extend_from_slice(text.as_bytes());
+            }
+            FormValue::File(path) => {
+                let (content_type, data) = match path {
+                    Some(path) => {
+                        let data = fs::read(path).map_err(|e| match e.kind() {
+                            io::ErrorKind::NotFound => RequestError::NotFound(path.display().to_string()),
+                            _ => RequestError::Io(e),
+                        })?;
+                        (mime::guess(path, &data).to_string(), data)
+                    }
+                    None => ("application/octet-stream".to_string(), Vec::new()),
+                };
+                part.extend_from_slice(format!("; filename=\"{}\"\r\n", escape_name(&file_name(path))).as_bytes());
+                part.extend_from_slice(format!("Content-Type: {}\r\n\r\n", content_type).as_bytes());
+                part.extend_from_slice(&data);
+            }
+        }
+        parts.push(part);
+    }
+
+    let mut boundary = new_boundary();
+    while parts.iter().any(|part| contains(part, boundary.as_bytes())) {
+        boundary = new_boundary();
+    }
+    let mut body = Vec::new();
+    for part in parts {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        body.extend_from_slice(&part);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    return Ok((format!("multipart/form-data; boundary={}", boundary), body));
+}
+
+// The request a form submits: GET forms replace the query of the action
+// url, anything else is a POST with the entries encoded per `enctype`.
+// Gemini and gopher searches take a query that is just the percent-encoded
+// text, so a lone isindex entry is sent that way, with spaces kept apart
+// from "+".
+pub fn submission(
+    action: &Url,
+    method: &str,
+    enctype: &str,
+    entries: &[(String, FormValue)],
+) -> Result<Request, RequestError> {
+    if !method.eq_ignore_ascii_case("post") {
+        let mut url = action.clone();
+        match entries {
+            [(name, FormValue::Text(text))] if name == "isindex" && matches!(url.scheme.as_str(), "gemini" | "gopher") => {
+                url.set_query(Some(&percent_encode(text)));
+            }
+            _ => url.set_query(Some(&urlencode(entries))),
+        }
+        return Ok(Request::get(&url));
+    }
+    if enctype.eq_ignore_ascii_case("multipart/form-data") {
+        let (content_type, body) = multipart(entries)?;
+        return Ok(Request::post(action).body(&content_type, body));
+    }
+    let body = urlencode(entries).into_bytes();
+    return Ok(Request::post(action).body("application/x-www-form-urlencoded", body));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn text(name: &str, value: &str) -> (String, FormValue) {
+        return (name.to_string(), FormValue::Text(value.to_string()));
+    }
+
+    fn pairs(decoded: &[(String, String)]) -> Vec<(&str, &str)> {
+        return decoded.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+    }
+
+    #[test]
+    fn urlencoded() {
+        let entries = [text("q", "a b&c=d"), text("lang", "ü*-._~"), text("", "")];
+        assert_eq!(urlencode(&entries), "q=a+b%26c%3Dd&lang=%C3%BC*-._%7E&=");
+        let round_trip = urldecode(&urlencode(&entries));
+        assert_eq!(pairs(&round_trip), [("q", "a b&c=d"), ("lang", "ü*-._~"), ("", "")]);
+        // a plus in the query is a space, an encoded one is a plus
+        let query = urldecode("a=1+2%2B3&flag&&b=");
+        assert_eq!(pairs(&query), [("a", "1 2+3"), ("flag", ""), ("b", "")]);
+        assert_eq!(urlencode(&[text("isindex", "two words"), text("x", "1")]), "two+words&x=1");
+        let file = ("upload".to_string(), FormValue::File(Some(PathBuf::from("/tmp/notes.txt"))));
+        assert_eq!(urlencode(&[file]), "upload=notes.txt");
+    }
+
+    #[test]
+    fn multipart_bodies() {
+        let path = env::temp_dir().join(format!("bowser-test-form-{}.png", std::process::id()));
+        fs::write(&path, b"\x89PNG\r\n\x1a\n").unwrap();
+        let entries = [
+            text("say \"hi\"\r\n", "line one\r\nline two"),
+            ("picture".to_string(), FormValue::File(Some(path.clone()))),
+            ("nothing".to_string(), FormValue::File(None)),
+        ];
+        let (content_type, body) = multipart(&entries).unwrap();
+        let _ = fs::remove_file(&path);
+
+        let boundary = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+        let body = String::from_utf8_lossy(&body).to_string();
+        let parts: Vec<&str> = body.split(&format!("--{}", boundary)).collect();
+        assert_eq!(parts.len(), 5, "{}", body);
+        assert_eq!((parts[0], parts[4]), ("", "--\r\n"));
+        assert_eq!(
+            parts[1],
+            "\r\nContent-Disposition: form-data; name=\"say %22hi%22%0D%0A\"\r\n\r\nline one\r\nline two\r\n"
+        );
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        assert_eq!(
+            parts[2],
+            format!(
+                "\r\nContent-Disposition: form-data; name=\"picture\"; filename=\"{}\"\r\n\
+                 Content-Type: image/png\r\n\r\n\u{fffd}PNG\r\n\u{1a}\n\r\n",
+                file_name
+            )
+        );
+        assert_eq!(
+            parts[3],
+            "\r\nContent-Disposition: form-data; name=\"nothing\"; filename=\"\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n\r\n"
+        );
+
+        let missing = [("gone".to_string(), FormValue::File(Some(PathBuf::from("/no/such/file"))))];
+        assert!(matches!(multipart(&missing), Err(RequestError::NotFound(_))));
+    }
+
+    #[test]
+    fn boundaries_are_not_in_the_body() {
+        let (content_type, _) = multipart(&[text("a", "1")]).unwrap();
+        let taken = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+        // a value holding one boundary cannot be cut short by the next
+        let (content_type, body) = multipart(&[text("a", taken)]).unwrap();
+        let boundary = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+        assert_ne!(boundary, taken);
+        assert_eq!(String::from_utf8(body).unwrap().matches(boundary).count(), 2);
+    }
+
+    #[test]
+    fn submissions() {
+        let action = Url::parse("http://example.com/search?old=1#results").unwrap();
+        let entries = [text("q", "rust lang")];
+        let request = submission(&action, "get", "multipart/form-data", &entries).unwrap();
+        assert_eq!((request.method.as_str(), request.body.is_none()), ("GET", true));
+        assert_eq!(request.url.to_string(), "http://example.com/search?q=rust+lang#results");
+
+        let request = submission(&action, "POST", "", &entries).unwrap();
+        assert_eq!((request.method.as_str(), request.url.to_string()), ("POST", action.to_string()));
+        assert_eq!(request.headers.get("content-type"), Some("application/x-www-form-urlencoded"));
+        assert_eq!(request.body.as_deref(), Some(&b"q=rust+lang"[..]));
+
+        let request = submission(&action, "post", "Multipart/Form-Data", &entries).unwrap();
+        assert!(request.headers.get("content-type").unwrap().starts_with("multipart/form-data; boundary="));
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{Read, Write};
+
+use super::transport::Transport;
+use super::url::{percent_decode_str, percent_encode_path};
+use super::{cancelled, charset, mime, HeaderMap, RequestError, Response, Stores, Url};
+use crate::html::escape;
+
+const DEFAULT_PORT: u16 = 70;
+
+// Item types we can follow, with the label shown before them in menus
+const ITEM_TYPES: [(char, &str); 6] = [
+    ('0', "TXT"),
+    ('1', "DIR"),
+    ('7', "ASK"),
+    ('h', "HTM"),
+    ('I', "IMG"),
+    ('g', "GIF"),
+];
+
+// The item type and selector a gopher url points at (RFC 4266). An empty
+// path is the server's root menu, and a search string follows a tab,
+// written %09, or comes in the query when a search form was submitted.
+fn parse_url(url: &Url) -> (char, String, Option<String>) {
+    let path = percent_decode_str(&url.path);
+    let mut chars = path.trim_start_matches('/').chars();
+    let kind = chars.next().unwrap_or('1');
+    let selector = chars.as_str();
+    let query = url.query.as_ref().map(|query| percent_decode_str(query));
+    match selector.split_once('\t') {
+        Some((selector, search)) => { return (kind, selector.to_string(), Some(search.to_string()).or(query)); }
+        None => { return (kind, selector.to_string(), query); }
+    }
+}
+
+// The gopher url for a menu item
+fn item_url(kind: char, selector: &str, host: &str, port: &str) -> String {
+    let port = match port.trim() {
+        "" | "70" => String::new(),
+        port => format!(":{}", port),
+    };
+    return format!("gopher://{}{}/{}{}", host, port, kind, percent_encode_path(selector));
+}
+
+// Renders a menu as a list of links. Info lines are plain text, and items
+// of types we cannot follow are shown without a link.
+fn menu(url: &Url, text: &str) -> String {
+    let mut html = format!("<html><body><h1>{}</h1><pre>", escape(&url.host_port()));
+    for line in text.lines() {
+        if line == "." {
+            break;
+        }
+        let mut chars = line.chars();
+        let kind = match chars.next() {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let mut fields = chars.as_str().split('\t');
+        let display = escape(fields.next().unwrap_or(""));
+        let selector = fields.next().unwrap_or("");
+        let host = fields.next().unwrap_or("");
+        let port = fields.next().unwrap_or("");
+        let label = ITEM_TYPES.iter().find(|(known, _)| *known == kind).map(|(_, label)| *label);
+        let href = match (kind, selector.strip_prefix("URL:")) {
+            // links to the web hide behind html items
+            ('h', Some(web)) => web.to_string(),
+            _ => item_url(kind, selector, host, port),
+        };
+        match label {
+            Some(label) if !host.is_empty() => {
+                html.push_str(&format!("{}  <a href=\"{}\">{}</a>\n", label, escape(&href), display));
+            }
+            // info and error lines
+            _ if matches!(kind, 'i' | '3') => html.push_str(&format!("     {}\n", display)),
+            _ => html.push_str(&format!("???  {}\n", display)),
+        }
+    }
+    html.push_str("</pre></body></html>");
+    return html;
+}
+
+// The page for a search item opened without a search string
+fn search_page(url: &Url) -> String {
+    let mut action = url.clone();
+    action.set_query(None);
+    action.set_fragment(None);
+    return format!(
+        "<html><body><h1>Search {}</h1><form action=\"{}\"><input name=\"isindex\"> <input type=\"submit\" value=\"Search\"></form></body></html>",
+        escape(&url.host_port()),
+        escape(&action.to_string())
+    );
+}
+
+// A text item ends with a line holding a single ".", and lines that start
+// with a dot have another one added in front
+fn unescape_text(text: &str) -> String {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        if line == "." {
+            break;
+        }
+        lines.push(line.strip_prefix("..").map(|rest| format!(".{}", rest)).unwrap_or_else(|| line.to_string()));
+    }
+    return lines.join("\n") + "\n";
+}
+
+// Sends the selector, and the search string after a tab, then reads until
+// the server closes the connection (RFC 1436)
+fn fetch(
+    transport: &mut dyn Transport,
+    stores: Stores,
+    url: &Url,
+    selector: &str,
+    search: Option<&str>,
+) -> Result<Vec<u8>, RequestError> {
+    let port = url.port.unwrap_or(DEFAULT_PORT);
+    let mut socket = transport.connect_tunnel(url.hostname(), port)?;
+    if let Some(cancel) = stores.cancel {
+        cancel.watch(&socket)?;
+    }
+    let line = match search {
+        Some(search) => format!("{}\t{}\r\n", selector, search),
+        None => format!("{}\r\n", selector),
+    };
+    socket.write_all(line.as_bytes())?;
+    socket.flush()?;
+    let mut body = Vec::new();
+    let read = socket.read_to_end(&mut body);
+    if cancelled(stores) {
+        return Err(RequestError::Cancelled);
+    }
+    read?;
+    return Ok(body);
+}
+
+// Requests a gopher:// url. Menus and search results become HTML pages of
+// links, text items plain text, and other items are passed on as they are.
+pub fn request(url: &Url, transport: &mut dyn Transport, stores: Stores) -> Result<Response, RequestError> {
+    let (kind, selector, search) = parse_url(url);
+    let mut headers = HeaderMap::new();
+    if kind == '7' && search.is_none() {
+        headers.insert("Content-Type", "text/html; charset=utf-8");
+        return Ok(Response::ok(url, headers, search_page(url).into_bytes()));
+    }
+    let body = fetch(transport, stores, url, &selector, search.as_deref())?;
+    let (content_type, body) = match kind {
+        '1' | '7' => ("text/html; charset=utf-8", menu(url, &charset::decode("text/plain", &body)).into_bytes()),
+        '0' => ("text/plain; charset=utf-8", unescape_text(&charset::decode("text/plain", &body)).into_bytes()),
+        'h' => ("text/html", body),
+        'g' => ("image/gif", body),
+        'I' => (mime::from_magic(&body).unwrap_or("application/octet-stream"), body),
+        _ => ("application/octet-stream", body),
+    };
+    headers.insert("Content-Type", content_type);
+    return Ok(Response::ok(url, headers, body));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::transport::MockTransport;
+    use super::*;
+
+    const MENU: &[u8] = b"iWelcome to the hole\tfake\t(NULL)\t0\r\n\
+        0About this server\t/about.txt\texample.com\t70\r\n\
+        1Docs & more\t/docs dir\tother.example\t7070\r\n\
+        7Search\t/search\texample.com\t70\r\n\
+        hThe web\tURL:http://example.org/?a=1&b=2\texample.com\t70\r\n\
+        9Binary\t/file.bin\texample.com\t70\r\n\
+        3Not found\terror.host\t1\r\n\
+        xUnknown\t/x\texample.com\t70\r\n\
+        .\r\n\
+        iafter the end\t\t\t\r\n";
+
+    fn get(transport: &mut MockTransport, url: &str) -> Response {
+        return request(&Url::parse(url).unwrap(), transport, Stores::default()).unwrap();
+    }
+
+    fn sent(transport: &MockTransport) -> String {
+        assert_eq!(transport.connections().len(), 1);
+        return String::from_utf8(transport.connections()[0].sent()).unwrap();
+    }
+
+    #[test]
+    fn menus() {
+        let mut transport = MockTransport::new();
+        transport.respond("example.com", 70, MENU);
+        let response = get(&mut transport, "gopher://example.com");
+        assert_eq!(sent(&transport), "\r\n");
+        assert_eq!(response.headers.get("content-type"), Some("text/html; charset=utf-8"));
+        let lines: Vec<String> = response.text().lines().map(String::from).collect();
+        assert_eq!(
+            lines,
+            [
+                "<html><body><h1>example.com</h1><pre>     Welcome to the hole",
+                "TXT  <a href=\"gopher://example.com/0/about.txt\">About this server</a>",
+                "DIR  <a href=\"gopher://other.example:7070/1/docs%20dir\">Docs &amp; more</a>",
+                "ASK  <a href=\"gopher://example.com/7/search\">Search</a>",
+                "HTM  <a href=\"http://example.org/?a=1&amp;b=2\">The web</a>",
+                "???  Binary",
+                "     Not found",
+                "???  Unknown",
+                "</pre></body></html>",
+            ]
+        );
+    }
+
+    #[test]
+    fn text() {
+        let mut transport = MockTransport::new();
+        transport.respond("example.com", 7070, b"first\r\n..dotted\r\n.\r\nignored\r\n");
+        let response = get(&mut transport, "gopher://example.com:7070/0/about.txt");
+        assert_eq!(sent(&transport), "/about.txt\r\n");
+        assert_eq!(response.headers.get("content-type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(response.text(), "first\n.dotted\n");
+    }
+
+    #[test]
+    fn searches() {
+        // without a search string there is only a form to ask for one
+        let mut transport = MockTransport::new();
+        let response = get(&mut transport, "gopher://example.com/7/search");
+        assert!(transport.connections().is_empty());
+        let html = response.text();
+        assert!(html.contains("<form action=\"gopher://example.com/7/search\"><input name=\"isindex\">"), "{}", html);
+
+        // as submitted by that form
+        let mut transport = MockTransport::new();
+        transport.respond("example.com", 70, MENU);
+        let response = get(&mut transport, "gopher://example.com/7/search?rust%20lang");
+        assert_eq!(sent(&transport), "/search\trust lang\r\n");
+        assert!(response.text().contains("<a href=\"gopher://example.com/0/about.txt\">"));
+
+        // or written into the url after a tab
+        let mut transport = MockTransport::new();
+        transport.respond("example.com", 70, MENU);
+        get(&mut transport, "gopher://example.com/7/search%09rust");
+        assert_eq!(sent(&transport), "/search\trust\r\n");
+    }
+
+    #[test]
+    fn other_items() {
+        let mut transport = MockTransport::new();
+        transport
+            .respond("example.com", 70, b"<p>hi</p>")
+            .respond("example.com", 70, b"\x89PNG\r\n\x1a\n....")
+            .respond("example.com", 70, b"\x00\x01");
+        assert_eq!(get(&mut transport, "gopher://example.com/h/page.html").headers.get("content-type"), Some("text/html"));
+        assert_eq!(get(&mut transport, "gopher://example.com/I/pic").headers.get("content-type"), Some("image/png"));
+        let response = get(&mut transport, "gopher://example.com/9/file.bin");
+        assert_eq!(response.headers.get("content-type"), Some("application/octet-stream"));
+        assert_eq!(response.body, b"\x00\x01");
+    }
+}
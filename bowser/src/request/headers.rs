@@ -0,0 +1,104 @@
+// Header names compare case-insensitively, values are kept exactly as they
+// were received, and a name may appear any number of times in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        return HeaderMap { entries: Vec::new() };
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        return self
+            .entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str());
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        return self
+            .entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str());
+    }
+
+    // All values for a list-valued header, joined with commas per RFC 9110
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).collect();
+        if values.is_empty() {
+            return None;
+        }
+        return Some(values.join(", "));
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        return self.get(name).is_some();
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    // Replaces every existing value for the name
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        return self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_ignore_case() {
+        let mut headers = HeaderMap::new();
+        headers.append("Content-Type", "Text/HTML; Charset=UTF-8");
+        assert_eq!(headers.get("content-type"), Some("Text/HTML; Charset=UTF-8"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("Text/HTML; Charset=UTF-8"));
+        assert!(headers.contains_key("content-TYPE"));
+        assert_eq!(headers.get("content-length"), None);
+
+        // insert replaces every spelling, and keeps the one it was given
+        headers.append("content-type", "text/plain");
+        headers.insert("CONTENT-type", "image/png");
+        assert_eq!(headers.iter().collect::<Vec<_>>(), [("CONTENT-type", "image/png")]);
+        headers.remove("Content-Type");
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn repeated_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Vary", "Accept");
+        headers.append("set-cookie", "b=2, c=3");
+        headers.append("VARY", "Cookie");
+        // the first value, then every value in the order received
+        assert_eq!(headers.get("set-cookie"), Some("a=1"));
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), ["a=1", "b=2, c=3"]);
+        assert_eq!(headers.get_joined("vary").as_deref(), Some("Accept, Cookie"));
+        assert_eq!(headers.get_joined("warning"), None);
+        assert_eq!(headers.len(), 4);
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use super::about;
+use super::date::{format_timestamp, now};
+use super::Url;
+use crate::html::escape;
+use crate::profile;
+
+const MAX_VISITS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct Visit {
+    pub url: String,
+    pub time: i64,
+}
+
+// Pages visited, oldest first, persisted to a file in the profile directory
+pub struct History {
+    visits: Mutex<Vec<Visit>>,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    pub fn new(file: Option<PathBuf>) -> History {
+        let visits = file.as_ref().and_then(|file| fs::read_to_string(file).ok()).map(|text| load(&text));
+        return History { visits: Mutex::new(visits.unwrap_or_default()), file };
+    }
+
+    // Internal pages are not worth remembering
+    pub fn visit(&self, url: &Url) {
+        if url.scheme == "about" {
+            return;
+        }
+        let mut visits = self.visits.lock().unwrap();
+        visits.push(Visit { url: url.to_string(), time: now() });
+        if visits.len() > MAX_VISITS {
+            let excess = visits.len() - MAX_VISITS;
+            visits.drain(..excess);
+        }
+        self.save(&visits);
+    }
+
+    // Most recent first
+    pub fn all(&self) -> Vec<Visit> {
+        return self.visits.lock().unwrap().iter().rev().cloned().collect();
+    }
+
+    pub fn clear(&self) {
+        let mut visits = self.visits.lock().unwrap();
+        visits.clear();
+        self.save(&visits);
+    }
+
+    fn save(&self, visits: &[Visit]) {
+        if let Some(file) = &self.file {
+            let text: String = visits.iter().map(|visit| format!("{}\t{}\n", visit.time, visit.url)).collect();
+            if let Err(e) = fs::write(file, text) {
+                println!("Failed to save history to {}: {}", file.display(), e);
+            }
+        }
+    }
+}
+
+// One visit per line, the time then the url, tab separated
+fn load(text: &str) -> Vec<Visit> {
+    return text
+        .lines()
+        .filter_map(|line| {
+            let (time, url) = line.split_once('\t')?;
+            return Some(Visit { url: url.to_string(), time: time.parse().ok()? });
+        })
+        .collect();
+}
+
+// The history shared by every window, stored in the profile directory
+pub fn shared() -> &'static History {
+    static HISTORY: OnceLock<History> = OnceLock::new();
+    return HISTORY.get_or_init(|| History::new(profile::path("history.txt")));
+}
+
+// The about:history page, most recent visit first. ?clear forgets them all.
+pub fn page(history: &History, query: Option<&str>) -> String {
+    if query.is_some_and(|query| query.split('&').any(|action| action == "clear")) {
+        history.clear();
+    }
+    let visits = history.all();
+    let mut html = String::from("<html><body><h1>History</h1>");
+    if visits.is_empty() {
+        html.push_str("<p>No pages have been visited.</p></body></html>");
+        return html;
+    }
+    html.push_str(&format!("<p><a href=\"{}\">Clear history</a></p><pre>", escape(&about::action_url("history", "clear"))));
+    for visit in &visits {
+        let url = escape(&visit.url);
+        html.push_str(&format!("{}  <a href=\"{}\">{}</a>\n", format_timestamp(visit.time), url, url));
+    }
+    html.push_str("</pre></body></html>");
+    return html;
+}
@@ -0,0 +1,229 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use super::date::now;
+use super::{HeaderMap, Url};
+use crate::profile;
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub host: String,
+    pub expires: i64,
+    pub include_subdomains: bool,
+}
+
+// Parses a Strict-Transport-Security value (RFC 6797 6.1) into max-age and
+// includeSubDomains. None when it is malformed or lacks max-age.
+fn parse_header(value: &str) -> Option<(i64, bool)> {
+    let mut max_age = None;
+    let mut include_subdomains = false;
+    for directive in value.split(';') {
+        let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+        let value = value.trim().trim_matches('"');
+        match name.trim().to_lowercase().as_str() {
+            "max-age" if max_age.is_none() => max_age = Some(value.parse::<i64>().ok()?),
+            "max-age" => { return None; }
+            "includesubdomains" => include_subdomains = true,
+            _ => {}
+        }
+    }
+    return Some((max_age?, include_subdomains));
+}
+
+// Hosts that asked to only ever be reached over https, persisted to a file
+// in the profile directory
+pub struct Hsts {
+    policies: Mutex<Vec<Policy>>,
+    file: Option<PathBuf>,
+}
+
+impl Hsts {
+    pub fn new(file: Option<PathBuf>) -> Hsts {
+        let policies = file.as_ref().and_then(|file| fs::read_to_string(file).ok()).map(|text| load(&text));
+        return Hsts { policies: Mutex::new(policies.unwrap_or_default()), file };
+    }
+
+    // Remembers the Strict-Transport-Security header of a response. Only
+    // headers that came over https count, and never for IP addresses.
+    // max-age=0 forgets the host.
+    pub fn store_response(&self, url: &Url, headers: &HeaderMap) {
+        let host = url.hostname().to_lowercase();
+        if url.scheme != "https" || host.starts_with('[') || host.parse::<IpAddr>().is_ok() {
+            return;
+        }
+        let (max_age, include_subdomains) = match headers.get("strict-transport-security").and_then(parse_header) {
+            Some(policy) => policy,
+            None => { return; }
+        };
+        let mut policies = self.policies.lock().unwrap();
+        policies.retain(|policy| policy.host != host);
+        if max_age > 0 {
+            policies.push(Policy { host, expires: now().saturating_add(max_age), include_subdomains });
+        }
+        self.save(&policies);
+    }
+
+    // Whether requests to `host` must use https, because of its own policy
+    // or one of a parent domain that includes subdomains
+    pub fn is_secure_only(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        let now = now();
+        return self.policies.lock().unwrap().iter().filter(|policy| policy.expires > now).any(|policy| {
+            return host == policy.host
+                || (policy.include_subdomains
+                    && host.ends_with(&policy.host)
+                    && host[..host.len() - policy.host.len()].ends_with('.'));
+        });
+    }
+
+    // The https url to request instead of an http one to a host that has
+    // to be reached securely
+    pub fn upgrade(&self, url: &Url) -> Option<Url> {
+        if url.scheme != "http" || !self.is_secure_only(url.hostname()) {
+            return None;
+        }
+        let mut upgraded = url.clone();
+        upgraded.scheme = "https".to_string();
+        // an explicit port 80 becomes 443, any other stays
+        if upgraded.port == Some(80) {
+            upgraded.port = None;
+        }
+        return Some(upgraded);
+    }
+
+    pub fn all(&self) -> Vec<Policy> {
+        let now = now();
+        return self.policies.lock().unwrap().iter().filter(|policy| policy.expires > now).cloned().collect();
+    }
+
+    pub fn clear(&self) {
+        let mut policies = self.policies.lock().unwrap();
+        policies.clear();
+        self.save(&policies);
+    }
+
+    fn save(&self, policies: &[Policy]) {
+        if let Some(file) = &self.file {
+            let text: String = policies
+                .iter()
+                .map(|policy| format!("{}\t{}\t{}\n", policy.expires, policy.include_subdomains, policy.host))
+                .collect();
+            if let Err(e) = fs::write(file, text) {
+                println!("Failed to save HSTS hosts to {}: {}", file.display(), e);
+            }
+        }
+    }
+}
+
+// One host per line: when its policy expires, whether it covers
+// subdomains, then the host, tab separated
+fn load(text: &str) -> Vec<Policy> {
+    let now = now();
+    return text
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 3 {
+                return None;
+            }
+            return Some(Policy {
+                host: fields[2].to_string(),
+                expires: fields[0].parse().ok()?,
+                include_subdomains: fields[1] == "true",
+            });
+        })
+        .filter(|policy| policy.expires > now)
+        .collect();
+}
+
+// The HSTS hosts shared by every request, stored in the profile directory
+pub fn shared() -> &'static Hsts {
+    static HSTS: OnceLock<Hsts> = OnceLock::new();
+    return HSTS.get_or_init(|| Hsts::new(profile::path("hsts.txt")));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        return Url::parse(url).unwrap();
+    }
+
+    fn header(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.append("Strict-Transport-Security", value);
+        return headers;
+    }
+
+    #[test]
+    fn parses_headers() {
+        assert_eq!(parse_header("max-age=31536000"), Some((31536000, false)));
+        assert_eq!(parse_header(" Max-Age=\"60\" ; includeSubDomains ; preload"), Some((60, true)));
+        assert_eq!(parse_header("max-age=0"), Some((0, false)));
+        // a directive given twice makes the whole header invalid
+        assert_eq!(parse_header("max-age=60; max-age=120"), None);
+        assert_eq!(parse_header("includeSubDomains"), None);
+        assert_eq!(parse_header("max-age=soon"), None);
+    }
+
+    #[test]
+    fn upgrades() {
+        let hsts = Hsts::new(None);
+        hsts.store_response(&url("https://example.com/"), &header("max-age=600; includeSubDomains"));
+        hsts.store_response(&url("https://other.org/"), &header("max-age=600"));
+
+        let upgraded = |address: &str| hsts.upgrade(&url(address)).map(|url| url.to_string());
+        assert_eq!(upgraded("http://example.com/a?b").as_deref(), Some("https://example.com/a?b"));
+        // port 80 becomes the https default, any other port is kept
+        assert_eq!(upgraded("http://example.com:80/").as_deref(), Some("https://example.com/"));
+        assert_eq!(upgraded("http://example.com:8080/").as_deref(), Some("https://example.com:8080/"));
+        assert_eq!(upgraded("http://www.EXAMPLE.com/").as_deref(), Some("https://www.example.com/"));
+        // without includeSubDomains only the host itself is covered
+        assert_eq!(upgraded("http://other.org/").as_deref(), Some("https://other.org/"));
+        assert_eq!(upgraded("http://www.other.org/"), None);
+        assert_eq!(upgraded("http://notexample.com/"), None);
+        assert_eq!(upgraded("https://example.com/"), None);
+    }
+
+    #[test]
+    fn stores_responses() {
+        let hsts = Hsts::new(None);
+        // only over https, and never for IP addresses
+        hsts.store_response(&url("http://example.com/"), &header("max-age=600"));
+        hsts.store_response(&url("https://127.0.0.1/"), &header("max-age=600"));
+        hsts.store_response(&url("https://[::1]/"), &header("max-age=600"));
+        assert!(hsts.all().is_empty());
+
+        hsts.store_response(&url("https://example.com/"), &header("max-age=600"));
+        assert!(hsts.is_secure_only("example.com"));
+        // max-age=0 forgets the host
+        hsts.store_response(&url("https://example.com/"), &header("max-age=0"));
+        assert!(!hsts.is_secure_only("example.com"));
+    }
+
+    #[test]
+    fn loads() {
+        let later = now() + 600;
+        let text = format!(
+            "{}\ttrue\texample.com\n{}\tfalse\texpired.com\nnot a policy\n{}\tfalse\tother.org\n",
+            later,
+            now() - 1,
+            later
+        );
+        let policies = load(&text);
+        let hosts: Vec<(&str, bool)> =
+            policies.iter().map(|policy| (policy.host.as_str(), policy.include_subdomains)).collect();
+        assert_eq!(hosts, [("example.com", true), ("other.org", false)]);
+
+        // what is saved loads back the same
+        let file = std::env::temp_dir().join(format!("bowser-test-hsts-{}", std::process::id()));
+        let hsts = Hsts::new(Some(file.clone()));
+        hsts.store_response(&url("https://example.com/"), &header("max-age=600; includeSubDomains"));
+        let loaded = Hsts::new(Some(file.clone()));
+        assert!(loaded.is_secure_only("www.example.com"));
+        let _ = fs::remove_file(file);
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::{self, BufRead, Read, Take};
+
+use super::{HeaderMap, RequestError};
+
+fn invalid_data(message: String) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message);
+}
+
+fn parse_status_line(line: &str) -> Result<(String, u16, String), RequestError> {
+    let malformed = || RequestError::MalformedStatusLine(line.trim_end().to_string());
+    let mut split = line.trim_end().splitn(3, ' ');
+    let version = split.next().ok_or_else(malformed)?;
+    if !version.starts_with("HTTP/") {
+        return Err(malformed());
+    }
+    let status = split
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(malformed)?;
+    let reason = split.next().unwrap_or("").to_string();
+    return Ok((version.to_string(), status, reason));
+}
+
+// Reads "name: value" lines up to and including the blank line that ends them
+pub fn read_headers<R: BufRead>(reader: &mut R) -> io::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match line.as_str() {
+            "\r\n" | "\n" => { break; }
+            "" => { return Err(invalid_data("unexpected end of headers".to_string())); }
+            data => {
+                let (header, value) = data
+                    .split_once(':')
+                    .ok_or_else(|| invalid_data(format!("bad header {:?}", data.trim_end())))?;
+                headers.append(header.trim(), value.trim());
+            }
+        }
+    }
+    return Ok(headers);
+}
+
+// Reads the status line and headers, returning the version, status, reason
+// and headers
+pub fn read_head<R: BufRead>(reader: &mut R) -> Result<(String, u16, String, HeaderMap), RequestError> {
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let (version, status, reason) = parse_status_line(&status_line)?;
+
+    let headers = read_headers(reader).map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidData => RequestError::BadHeader(e.to_string()),
+        _ => RequestError::Io(e),
+    })?;
+    return Ok((version, status, reason, headers));
+}
+
+pub struct ChunkedReader<R> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+    pub trailers: HeaderMap,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        return ChunkedReader { inner, remaining: 0, done: false, trailers: HeaderMap::new() };
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "missing last chunk"));
+        }
+        // chunk extensions follow the size after a ';' and are ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        self.remaining = usize::from_str_radix(size, 16)
+            .map_err(|_| invalid_data(format!("bad chunk size {:?}", line.trim_end())))?;
+        if self.remaining == 0 {
+            self.trailers = read_headers(&mut self.inner)?;
+            self.done = true;
+        }
+        return Ok(());
+    }
+
+    fn end_chunk(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        self.inner.read_line(&mut line)?;
+        if line != "\r\n" && line != "\n" {
+            return Err(invalid_data("missing CRLF after chunk".to_string()));
+        }
+        return Ok(());
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !self.done {
+            self.next_chunk()?;
+        }
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        let max = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated chunk"));
+        }
+        self.remaining -= read;
+        if self.remaining == 0 {
+            self.end_chunk()?;
+        }
+        return Ok(read);
+    }
+}
+
+pub enum Body<R> {
+    Empty,
+    Length(Take<R>),
+    Chunked(ChunkedReader<R>),
+    Close(R),
+}
+
+impl<R: BufRead> Body<R> {
+    // Picks the framing of the body from the status and headers, following
+    // the message length rules of RFC 9112 section 6.3
+    pub fn new(reader: R, status: u16, headers: &HeaderMap) -> Result<Body<R>, RequestError> {
+        if (100..200).contains(&status) || status == 204 || status == 304 {
+            return Ok(Body::Empty);
+        }
+        if let Some(encoding) = headers.get_joined("transfer-encoding") {
+            let last = encoding.rsplit(',').next().unwrap_or("").trim();
+            if last.eq_ignore_ascii_case("chunked") {
+                return Ok(Body::Chunked(ChunkedReader::new(reader)));
+            }
+            return Ok(Body::Close(reader));
+        }
+
+        // repeated content-length headers are only acceptable if they agree
+        let mut length = None;
+        for value in headers.get_all("content-length").flat_map(|value| value.split(',')) {
+            let value = value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| RequestError::BadHeader(format!("content-length: {}", value)))?;
+            if length.is_some_and(|length| length != value) {
+                return Err(RequestError::BadHeader("conflicting content-length".to_string()));
+            }
+            length = Some(value);
+        }
+        match length {
+            Some(length) => { return Ok(Body::Length(reader.take(length))); }
+            None => { return Ok(Body::Close(reader)); }
+        }
+    }
+}
+
+impl<R: BufRead> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Body::Empty => { return Ok(0); }
+            Body::Length(reader) => {
+                let read = reader.read(buf)?;
+                if read == 0 && reader.limit() > 0 && !buf.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body shorter than content-length"));
+                }
+                return Ok(read);
+            }
+            Body::Chunked(reader) => { return reader.read(buf); }
+            Body::Close(reader) => { return reader.read(buf); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read};
+
+    use super::*;
+
+    fn head(bytes: &[u8]) -> Result<(String, u16, String, HeaderMap), RequestError> {
+        return read_head(&mut BufReader::new(bytes));
+    }
+
+    fn header_map(headers: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(name, value);
+        }
+        return map;
+    }
+
+    fn body(bytes: &[u8], status: u16, headers: &[(&str, &str)]) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        Body::new(BufReader::new(bytes), status, &header_map(headers)).unwrap().read_to_end(&mut body)?;
+        return Ok(body);
+    }
+
+    #[test]
+    fn status_line() {
+        let (version, status, reason, _) = head(b"HTTP/1.1 404 Not Found\r\n\r\n").unwrap();
+        assert_eq!((version.as_str(), status, reason.as_str()), ("HTTP/1.1", 404, "Not Found"));
+        // the reason phrase may be left out
+        let (_, status, reason, _) = head(b"HTTP/1.0 200\r\n\r\n").unwrap();
+        assert_eq!((status, reason.as_str()), (200, ""));
+    }
+
+    #[test]
+    fn malformed_status_line() {
+        for line in ["ICY 200 OK\r\n\r\n", "HTTP/1.1 OK\r\n\r\n", "HTTP/1.1\r\n\r\n", ""] {
+            match head(line.as_bytes()) {
+                Err(RequestError::MalformedStatusLine(_)) => {}
+                other => panic!("{:?} parsed as {:?}", line, other),
+            }
+        }
+    }
+
+    #[test]
+    fn headers() {
+        let (_, _, _, headers) =
+            head(b"HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nContent-Type:  text/html \r\nset-cookie: b=2\r\n\r\n").unwrap();
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+        match head(b"HTTP/1.1 200 OK\r\nno colon here\r\n\r\n") {
+            Err(RequestError::BadHeader(_)) => {}
+            other => panic!("bad header parsed as {:?}", other),
+        }
+        match head(b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n") {
+            Err(RequestError::BadHeader(_)) => {}
+            other => panic!("truncated headers parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn content_length_body() {
+        assert_eq!(body(b"hello, and the next response", 200, &[("Content-Length", "5")]).unwrap(), b"hello");
+        assert_eq!(body(b"hello", 200, &[("Content-Length", "5"), ("Content-Length", "5")]).unwrap(), b"hello");
+        assert_eq!(body(b"hel", 200, &[("Content-Length", "5")]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        let conflicting = header_map(&[("Content-Length", "5"), ("Content-Length", "6")]);
+        assert!(matches!(Body::new(&b""[..], 200, &conflicting), Err(RequestError::BadHeader(_))));
+    }
+
+    #[test]
+    fn chunked_body() {
+        let chunked = b"4\r\nWiki\r\n5;name=value\r\npedia\r\n0\r\nExpires: never\r\n\r\nleftover";
+        let mut reader = ChunkedReader::new(BufReader::new(&chunked[..]));
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, b"Wikipedia");
+        assert_eq!(reader.trailers.get("expires"), Some("never"));
+
+        // chunked wins over content-length
+        let headers = [("Transfer-Encoding", "gzip, chunked"), ("Content-Length", "2")];
+        assert_eq!(body(chunked, 200, &headers).unwrap(), b"Wikipedia");
+        assert_eq!(body(b"4\r\nWi", 200, &headers).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(body(b"4\r\nWiki\r\n", 200, &headers).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(body(b"zz\r\n", 200, &headers).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn close_delimited_body() {
+        assert_eq!(body(b"until the server hangs up", 200, &[]).unwrap(), b"until the server hangs up");
+        // a transfer coding other than chunked also ends at close
+        assert_eq!(body(b"gzipped", 200, &[("Transfer-Encoding", "gzip")]).unwrap(), b"gzipped");
+    }
+
+    #[test]
+    fn bodiless_statuses() {
+        for status in [100, 204, 304] {
+            assert_eq!(body(b"ignored", status, &[("Content-Length", "7")]).unwrap(), b"");
+        }
+    }
+}
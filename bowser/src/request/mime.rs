@@ -0,0 +1,273 @@
+use std::path::Path;
+
+const EXTENSIONS: [(&str, &str); 22] = [
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("xhtml", "application/xhtml+xml"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("rs", "text/plain"),
+    ("toml", "text/plain"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gmi", "text/gemini"),
+];
+
+// How much of a body is looked at to sniff its type
+pub const SNIFF_LENGTH: usize = 1445;
+
+// Signatures that identify a binary format regardless of the file name
+const SIGNATURES: [(&[u8], &str); 7] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+];
+
+pub fn from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    return EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, mime)| *mime);
+}
+
+pub fn from_magic(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    return SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+        .map(|(_, mime)| *mime);
+}
+
+// Text has no NUL bytes or other control characters besides whitespace
+pub fn looks_like_text(bytes: &[u8]) -> bool {
+    return !bytes
+        .iter()
+        .take(1024)
+        .any(|b| *b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b));
+}
+
+// The type and subtype of a Content-Type value, without parameters
+pub fn essence(content_type: &str) -> String {
+    return content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+}
+
+// Tags that mark a body as HTML when followed by a space or ">", from the
+// WHATWG MIME Sniffing spec
+const HTML_TAGS: [&[u8]; 17] = [
+    b"<!DOCTYPE HTML",
+    b"<HTML",
+    b"<HEAD",
+    b"<SCRIPT",
+    b"<IFRAME",
+    b"<H1",
+    b"<DIV",
+    b"<FONT",
+    b"<TABLE",
+    b"<A",
+    b"<STYLE",
+    b"<TITLE",
+    b"<B",
+    b"<BODY",
+    b"<BR",
+    b"<P",
+    b"<!--",
+];
+
+// Content-Type headers that old Apache servers sent for every file, which
+// therefore cannot be trusted to mean text
+const APACHE_DEFAULTS: [&str; 4] = [
+    "text/plain",
+    "text/plain; charset=ISO-8859-1",
+    "text/plain; charset=iso-8859-1",
+    "text/plain; charset=UTF-8",
+];
+
+const BOMS: [&[u8]; 3] = [b"\xfe\xff", b"\xff\xfe", b"\xef\xbb\xbf"];
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    return bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix);
+}
+
+fn skip_whitespace(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(bytes.len());
+    return &bytes[start..];
+}
+
+fn looks_like_html(bytes: &[u8]) -> bool {
+    let bytes = skip_whitespace(bytes);
+    return HTML_TAGS.iter().any(|tag| {
+        starts_with_ignore_case(bytes, tag) && matches!(bytes.get(tag.len()), Some(b' ' | b'>'))
+    });
+}
+
+fn looks_like_svg(bytes: &[u8]) -> bool {
+    let bytes = skip_whitespace(bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes));
+    if starts_with_ignore_case(bytes, b"<svg") {
+        return true;
+    }
+    let head = &bytes[..bytes.len().min(1024)];
+    return starts_with_ignore_case(bytes, b"<?xml") && head.windows(4).any(|window| window.eq_ignore_ascii_case(b"<svg"));
+}
+
+// The rules for identifying an unknown MIME type. Types that could run
+// script, HTML and XML, are only sniffed when `scriptable` is set.
+fn sniff_unknown(bytes: &[u8], scriptable: bool) -> &'static str {
+    if scriptable {
+        if looks_like_html(bytes) {
+            return "text/html";
+        }
+        if looks_like_svg(bytes) {
+            return "image/svg+xml";
+        }
+        if bytes.starts_with(b"<?xml") {
+            return "text/xml";
+        }
+    }
+    if let Some(mime) = from_magic(bytes) {
+        return mime;
+    }
+    if bytes.starts_with(b"%!PS-Adobe-") {
+        return "application/postscript";
+    }
+    if BOMS.iter().any(|bom| bytes.starts_with(bom)) || looks_like_text(bytes) {
+        return "text/plain";
+    }
+    return "application/octet-stream";
+}
+
+// WHATWG MIME type sniffing: picks the type to render a body as from its
+// Content-Type, if any, and its first bytes. With `no_sniff`, set by
+// X-Content-Type-Options: nosniff, a declared type is always believed.
+pub fn sniff(content_type: Option<&str>, no_sniff: bool, bytes: &[u8]) -> String {
+    let declared = content_type.map(essence).unwrap_or_default();
+    if matches!(declared.as_str(), "" | "unknown/unknown" | "application/unknown" | "*/*") {
+        return sniff_unknown(bytes, !no_sniff).to_string();
+    }
+    if no_sniff {
+        return declared;
+    }
+    if content_type.is_some_and(|content_type| APACHE_DEFAULTS.contains(&content_type.trim())) {
+        if BOMS.iter().any(|bom| bytes.starts_with(bom)) || looks_like_text(bytes) {
+            return "text/plain".to_string();
+        }
+        return sniff_unknown(bytes, false).to_string();
+    }
+    if declared.starts_with("image/") && declared != "image/svg+xml" {
+        if let Some(image) = from_magic(bytes).filter(|mime| mime.starts_with("image/")) {
+            return image.to_string();
+        }
+    }
+    return declared;
+}
+
+pub fn guess(path: &Path, bytes: &[u8]) -> &'static str {
+    if let Some(mime) = from_magic(bytes) {
+        return mime;
+    }
+    if let Some(mime) = from_extension(path) {
+        return mime;
+    }
+    if looks_like_text(bytes) {
+        return "text/plain";
+    }
+    return "application/octet-stream";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures() {
+        assert_eq!(from_magic(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(from_magic(b"GIF87a"), Some("image/gif"));
+        assert_eq!(from_magic(b"GIF89a...."), Some("image/gif"));
+        assert_eq!(from_magic(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(from_magic(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(from_magic(b"PK\x03\x04"), Some("application/zip"));
+        assert_eq!(from_magic(b"\x00\x00\x01\x00\x01\x00"), Some("image/x-icon"));
+        assert_eq!(from_magic(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some("image/webp"));
+        // a RIFF file that is not WebP, and a signature cut short
+        assert_eq!(from_magic(b"RIFF\x00\x00\x00\x00WAVEfmt "), None);
+        assert_eq!(from_magic(b"\x89PNG"), None);
+    }
+
+    #[test]
+    fn scriptable_types() {
+        assert_eq!(sniff(None, false, b"  \n<!doctype html><p>hi"), "text/html");
+        assert_eq!(sniff(Some("unknown/unknown"), false, b"<P>hi"), "text/html");
+        assert_eq!(sniff(None, false, b"<!-- note --><div>"), "text/html");
+        // a tag name must end at a space or ">"
+        assert_eq!(sniff(None, false, b"<pre>x</pre>"), "text/plain");
+        assert_eq!(sniff(None, false, b"\xef\xbb\xbf <svg xmlns=\"\">"), "image/svg+xml");
+        assert_eq!(sniff(None, false, b"<?xml version=\"1.0\"?>\n<svg>"), "image/svg+xml");
+        assert_eq!(sniff(None, false, b"<?xml version=\"1.0\"?><feed/>"), "text/xml");
+        // nosniff leaves an untyped body no way to run script
+        assert_eq!(sniff(None, true, b"<html><script>"), "text/plain");
+        assert_eq!(sniff(Some("*/*"), true, b"<svg>"), "text/plain");
+        assert_eq!(sniff(None, true, b"GIF89a"), "image/gif");
+    }
+
+    #[test]
+    fn declared_types() {
+        assert_eq!(sniff(Some("Text/HTML; charset=utf-8"), false, b"%PDF-"), "text/html");
+        assert_eq!(sniff(Some("application/json"), true, b"<html>"), "application/json");
+        // images are believed to be images, but of the kind the body says
+        assert_eq!(sniff(Some("image/png"), false, b"GIF89a"), "image/gif");
+        assert_eq!(sniff(Some("image/png"), false, b"%PDF-"), "image/png");
+        assert_eq!(sniff(Some("image/png"), true, b"GIF89a"), "image/png");
+        assert_eq!(sniff(Some("image/svg+xml"), false, b"\x89PNG\r\n\x1a\n"), "image/svg+xml");
+    }
+
+    #[test]
+    fn apache_defaults() {
+        assert_eq!(sniff(Some("text/plain"), false, b"<html><body>"), "text/plain");
+        assert_eq!(sniff(Some("text/plain; charset=UTF-8"), false, b"\x89PNG\r\n\x1a\n"), "image/png");
+        assert_eq!(sniff(Some("text/plain; charset=iso-8859-1"), false, b"\x00\x01\x02"), "application/octet-stream");
+        assert_eq!(sniff(Some("text/plain; charset=ISO-8859-1"), false, b"\xff\xfe\x00\x00"), "text/plain");
+        // any other text/plain is taken at its word
+        assert_eq!(sniff(Some("text/plain; charset=utf-16"), false, b"\x00\x01"), "text/plain");
+        assert_eq!(sniff(Some("text/plain"), true, b"\x89PNG\r\n\x1a\n"), "text/plain");
+    }
+
+    #[test]
+    fn text_or_binary() {
+        assert!(looks_like_text(b"tabs\tand\r\nlines\x0c\x1b[0m"));
+        assert!(!looks_like_text(b"nul\x00byte"));
+        assert_eq!(sniff(None, false, b"%!PS-Adobe-3.0"), "application/postscript");
+        assert_eq!(sniff(None, false, b"\xfe\xff\x00\x01"), "text/plain");
+        assert_eq!(sniff(None, false, b"\x7fELF\x02\x01\x01\x00"), "application/octet-stream");
+        assert_eq!(sniff(None, false, b""), "text/plain");
+    }
+
+    #[test]
+    fn guesses() {
+        assert_eq!(from_extension(Path::new("index.HTM")), Some("text/html"));
+        assert_eq!(from_extension(Path::new("/capsule/index.gmi")), Some("text/gemini"));
+        assert_eq!(from_extension(Path::new("Makefile")), None);
+        // the body wins over the name, then the name over the body being text
+        assert_eq!(guess(Path::new("photo.txt"), b"\xff\xd8\xff\xe0"), "image/jpeg");
+        assert_eq!(guess(Path::new("style.css"), b"\x00\x01"), "text/css");
+        assert_eq!(guess(Path::new("README"), b"plain words"), "text/plain");
+        assert_eq!(guess(Path::new("a.out"), b"\x7fELF\x00"), "application/octet-stream");
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use super::transport::{Cancel, Stream};
+use super::{HeaderMap, RequestError};
+
+pub const MAX_PER_ORIGIN: usize = 6;
+
+// How long an idle connection is trusted when the server gives no timeout
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// How long a checkout waits for a busy origin to free a connection, and how
+// often it looks up from the wait to see whether it was stopped
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+pub type Connection = BufReader<Box<dyn Stream>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Origin {
+    secure: bool,
+    host: String,
+    port: u16,
+    // the proxy the connection goes through, None when it is direct
+    proxy: Option<String>,
+}
+
+struct Idle {
+    conn: Connection,
+    since: Instant,
+    timeout: Duration,
+}
+
+impl Idle {
+    fn is_fresh(&self) -> bool {
+        return self.since.elapsed() < self.timeout;
+    }
+}
+
+#[derive(Default)]
+struct State {
+    idle: HashMap<Origin, Vec<Idle>>,
+    active: HashMap<Origin, usize>,
+}
+
+// Persistent connections shared between requests, keyed by origin and the
+// proxy they were opened through. At most
+// `max_per_origin` connections to an origin are in use at once; further
+// checkouts wait until one is handed back, or give up after `wait_timeout`.
+pub struct Pool {
+    state: Mutex<State>,
+    freed: Condvar,
+    max_per_origin: usize,
+    wait_timeout: Duration,
+}
+
+impl Pool {
+    pub fn new(max_per_origin: usize) -> Pool {
+        return Pool {
+            state: Mutex::new(State::default()),
+            freed: Condvar::new(),
+            max_per_origin: max_per_origin.max(1),
+            wait_timeout: WAIT_TIMEOUT,
+        };
+    }
+
+    // Claims a slot for the origin reached through `proxy`, handing over the
+    // most recently used idle connection when `reuse` is set and one has not
+    // timed out. Waiting for a slot ends early when `cancel` is cancelled.
+    pub fn checkout(
+        self: &Arc<Pool>,
+        secure: bool,
+        host: &str,
+        port: u16,
+        proxy: Option<String>,
+        reuse: bool,
+        cancel: Option<&Cancel>,
+    ) -> Result<Lease, RequestError> {
+        let origin = Origin { secure, host: host.to_string(), port, proxy };
+        let deadline = Instant::now() + self.wait_timeout;
+        let mut state = self.state.lock().unwrap();
+        while state.active.get(&origin).copied().unwrap_or(0) >= self.max_per_origin {
+            if cancel.is_some_and(Cancel::is_cancelled) {
+                return Err(RequestError::Cancelled);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(RequestError::TimedOut);
+            }
+            state = self.freed.wait_timeout(state, left.min(CANCEL_POLL)).unwrap().0;
+        }
+        *state.active.entry(origin.clone()).or_insert(0) += 1;
+
+        let active = state.active[&origin];
+        let idle = state.idle.entry(origin.clone()).or_default();
+        idle.retain(Idle::is_fresh);
+        let conn = if reuse { idle.pop().map(|idle| idle.conn) } else { None };
+        // a fresh socket must not push the origin over its limit
+        if conn.is_none() && active + idle.len() > self.max_per_origin {
+            idle.remove(0);
+        }
+        return Ok(Lease { pool: Arc::clone(self), origin, conn });
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().idle.clear();
+    }
+}
+
+// The default pool shared by every TCP transport
+pub fn shared() -> Arc<Pool> {
+    static POOL: OnceLock<Arc<Pool>> = OnceLock::new();
+    return Arc::clone(POOL.get_or_init(|| Arc::new(Pool::new(MAX_PER_ORIGIN))));
+}
+
+// A claimed slot in the pool, released when dropped
+pub struct Lease {
+    pool: Arc<Pool>,
+    origin: Origin,
+    conn: Option<Connection>,
+}
+
+impl Lease {
+    // The idle connection handed over at checkout, if any
+    pub fn take(&mut self) -> Option<Connection> {
+        return self.conn.take();
+    }
+
+    // Parks the connection for reuse, honoring the Keep-Alive header
+    pub fn keep(self, conn: Connection, headers: &HeaderMap) {
+        let mut timeout = DEFAULT_IDLE_TIMEOUT;
+        for param in headers.get_joined("keep-alive").unwrap_or_default().split(',') {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+            match (name.as_str(), value.parse::<u64>()) {
+                // give up a second early so we never race the server's close
+                ("timeout", Ok(secs)) => timeout = Duration::from_secs(secs.saturating_sub(1)),
+                ("max", Ok(0)) => return,
+                _ => {}
+            }
+        }
+        let idle = Idle { conn, since: Instant::now(), timeout };
+        let mut state = self.pool.state.lock().unwrap();
+        state.idle.entry(self.origin.clone()).or_default().push(idle);
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut state = self.pool.state.lock().unwrap();
+        if let Some(active) = state.active.get_mut(&self.origin) {
+            *active -= 1;
+        }
+        self.pool.freed.notify_all();
+    }
+}
+
+// Whether the server is willing to keep the connection open after this
+// response. HTTP/1.1 persists unless told to close, HTTP/1.0 only when asked.
+pub fn persistent(version: &str, headers: &HeaderMap) -> bool {
+    let connection = headers.get_joined("connection").unwrap_or_default().to_lowercase();
+    let mut tokens = connection.split(',').map(str::trim);
+    if version == "HTTP/1.0" {
+        return tokens.any(|token| token == "keep-alive");
+    }
+    return !tokens.any(|token| token == "close");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::super::transport::{MockTransport, Transport};
+    use super::*;
+
+    fn checkout(pool: &Arc<Pool>, proxy: Option<&str>) -> Result<Lease, RequestError> {
+        return pool.checkout(false, "example.com", 80, proxy.map(String::from), true, None);
+    }
+
+    #[test]
+    fn routes_are_kept_apart() {
+        let pool = Arc::new(Pool::new(MAX_PER_ORIGIN));
+        let mut transport = MockTransport::new();
+        transport.respond("proxy", 3128, b"");
+        let conn = BufReader::new(transport.connect("proxy", 3128, false).unwrap());
+        checkout(&pool, Some("proxy:3128")).unwrap().keep(conn, &HeaderMap::new());
+
+        // a direct request may not reuse the socket to the proxy
+        assert!(checkout(&pool, None).unwrap().take().is_none());
+        assert!(checkout(&pool, Some("other:3128")).unwrap().take().is_none());
+        assert!(checkout(&pool, Some("proxy:3128")).unwrap().take().is_some());
+    }
+
+    #[test]
+    fn waits_for_a_free_slot() {
+        let mut pool = Pool::new(1);
+        pool.wait_timeout = Duration::from_millis(200);
+        let pool = Arc::new(pool);
+        let lease = checkout(&pool, None).unwrap();
+        assert!(matches!(checkout(&pool, None), Err(RequestError::TimedOut)));
+        // other origins are not held up
+        assert!(pool.checkout(false, "example.org", 80, None, true, None).is_ok());
+
+        let handed_back = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(lease);
+        });
+        assert!(checkout(&pool, None).is_ok());
+        handed_back.join().unwrap();
+    }
+
+    #[test]
+    fn stops_waiting_when_cancelled() {
+        let pool = Arc::new(Pool::new(1));
+        let _lease = checkout(&pool, None).unwrap();
+        let cancel = Cancel::new();
+        let stopper = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            stopper.cancel();
+        });
+        let started = Instant::now();
+        assert!(matches!(
+            pool.checkout(false, "example.com", 80, None, true, Some(&cancel)),
+            Err(RequestError::Cancelled)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,282 @@
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned};
+use std::io::{BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{env, fs};
+
+use super::transport::Stream;
+use super::RequestError;
+
+pub type TlsStream = StreamOwned<ClientConnection, Box<dyn Stream>>;
+
+// Path to a PEM bundle that replaces the system trust store. Falls back to
+// the BOWSER_CA_BUNDLE environment variable when unset.
+static CA_BUNDLE: Mutex<Option<PathBuf>> = Mutex::new(None);
+static CONFIG: Mutex<Option<Arc<ClientConfig>>> = Mutex::new(None);
+
+pub fn set_ca_bundle(path: &Path) {
+    *CA_BUNDLE.lock().unwrap() = Some(path.to_path_buf());
+    *CONFIG.lock().unwrap() = None;
+}
+
+fn load_bundle(path: &Path) -> Result<RootCertStore, RequestError> {
+    let bundle_error = |reason: String| {
+        RequestError::Tls(format!("CA bundle {}: {}", path.display(), reason))
+    };
+    let pem = fs::read(path).map_err(|e| bundle_error(e.to_string()))?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(pem.as_slice())) {
+        let cert = cert.map_err(|e| bundle_error(e.to_string()))?;
+        roots.add(cert).map_err(|e| bundle_error(e.to_string()))?;
+    }
+    if roots.is_empty() {
+        return Err(bundle_error("no certificates found".to_string()));
+    }
+    return Ok(roots);
+}
+
+fn load_system_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    if added == 0 {
+        println!("No system certificates found, using bundled roots");
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    }
+    return roots;
+}
+
+fn config() -> Result<Arc<ClientConfig>, RequestError> {
+    let mut config = CONFIG.lock().unwrap();
+    if let Some(config) = config.as_ref() {
+        return Ok(Arc::clone(config));
+    }
+
+    let bundle = CA_BUNDLE
+        .lock()
+        .unwrap()
+        .clone()
+        .or_else(|| env::var_os("BOWSER_CA_BUNDLE").map(PathBuf::from));
+    let roots = match bundle {
+        Some(path) => load_bundle(&path)?,
+        None => load_system_roots(),
+    };
+
+    let client_config = Arc::new(
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| RequestError::Tls(e.to_string()))?
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    );
+    *config = Some(Arc::clone(&client_config));
+    return Ok(client_config);
+}
+
+fn describe(error: &rustls::Error) -> String {
+    match error {
+        rustls::Error::InvalidCertificate(cert_error) => {
+            format!("invalid certificate ({:?})", cert_error)
+        }
+        rustls::Error::NoCertificatesPresented => "server presented no certificate".to_string(),
+        other => other.to_string(),
+    }
+}
+
+pub fn connect(host: &str, socket: Box<dyn Stream>) -> Result<TlsStream, RequestError> {
+    return handshake(host, socket, config()?);
+}
+
+// Accepts whatever certificate the server presents, for protocols that pin
+// certificates themselves instead of trusting CAs. Handshake signatures are
+// still checked, so the server must hold the key of the certificate it sent.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        return Ok(ServerCertVerified::assertion());
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        return verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms);
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        return verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms);
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        return self.0.signature_verification_algorithms.supported_schemes();
+    }
+}
+
+// Like connect, but any certificate is accepted and left in
+// `conn.peer_certificates()` for the caller to check
+pub fn connect_unverified(host: &str, socket: Box<dyn Stream>) -> Result<TlsStream, RequestError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| RequestError::Tls(e.to_string()))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+        .with_no_client_auth();
+    return handshake(host, socket, Arc::new(config));
+}
+
+fn handshake(host: &str, socket: Box<dyn Stream>, config: Arc<ClientConfig>) -> Result<TlsStream, RequestError> {
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|_| RequestError::Tls(format!("invalid server name {}", host)))?;
+    let conn = ClientConnection::new(config, server_name)
+        .map_err(|e| RequestError::Tls(format!("{}: {}", host, e)))?;
+    let mut stream = StreamOwned::new(conn, socket);
+
+    // drive the handshake up front so certificate problems surface here
+    // instead of as an opaque error on the first read
+    while stream.conn.is_handshaking() {
+        if let Err(e) = stream.conn.complete_io(&mut stream.sock) {
+            let reason = match e.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()) {
+                Some(tls_error) => describe(tls_error),
+                None if e.kind() == ErrorKind::UnexpectedEof => "connection closed".to_string(),
+                None => e.to_string(),
+            };
+            return Err(RequestError::Tls(format!("handshake with {} failed: {}", host, reason)));
+        }
+    }
+
+    return Ok(stream);
+}
+
+#[cfg(test)]
+pub mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use std::{env, fs, thread};
+
+    use super::*;
+
+    // the trust store is global, so tests that change it take turns
+    static TRUST: Mutex<()> = Mutex::new(());
+
+    // A certificate authority, as PEM, and a server configured with a
+    // certificate it issued for `names`
+    pub fn issue(names: &[&str]) -> (String, Arc<ServerConfig>) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.distinguished_name.push(DnType::CommonName, "bowser test CA");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let mut params = CertificateParams::new(names).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "bowser test server");
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.der().clone()], PrivateKeyDer::Pkcs8(key.serialize_der().into()))
+            .unwrap();
+        return (ca.pem(), Arc::new(config));
+    }
+
+    // Accepts one connection on a local port, reads a line from the client
+    // once the handshake is done and answers it with `reply`. Returns the
+    // port to connect to.
+    pub fn serve(config: Arc<ServerConfig>, reply: Vec<u8>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut stream = StreamOwned::new(ServerConnection::new(config).unwrap(), socket);
+            let mut byte = [0; 1];
+            while stream.read(&mut byte).is_ok_and(|read| read == 1) && byte[0] != b'\n' {}
+            let _ = stream.write_all(&reply);
+            stream.conn.send_close_notify();
+            let _ = stream.flush();
+        });
+        return port;
+    }
+
+    fn trust(ca_pem: &str) {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("bowser-test-ca-{}-{}.pem", std::process::id(), FILES.fetch_add(1, Ordering::Relaxed));
+        let path = env::temp_dir().join(name);
+        fs::write(&path, ca_pem).unwrap();
+        set_ca_bundle(&path);
+    }
+
+    fn connect_local(host: &str, port: u16) -> Result<TlsStream, RequestError> {
+        let socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        return connect(host, Box::new(socket));
+    }
+
+    #[test]
+    fn trusted_root() {
+        let _trust = TRUST.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (ca, server) = issue(&["localhost"]);
+        trust(&ca);
+        let port = serve(server, b"pong".to_vec());
+
+        let mut stream = connect_local("localhost", port).unwrap();
+        stream.write_all(b"ping\n").unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"pong");
+    }
+
+    #[test]
+    fn untrusted_root() {
+        let _trust = TRUST.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (ca, _) = issue(&["localhost"]);
+        let (_, server) = issue(&["localhost"]);
+        trust(&ca);
+        let port = serve(server, Vec::new());
+
+        match connect_local("localhost", port) {
+            Err(RequestError::Tls(reason)) => assert!(reason.contains("invalid certificate"), "{}", reason),
+            other => panic!("expected a TLS error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn wrong_host_name() {
+        let _trust = TRUST.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (ca, server) = issue(&["localhost"]);
+        trust(&ca);
+        let port = serve(server, Vec::new());
+
+        match connect_local("example.com", port) {
+            Err(RequestError::Tls(reason)) => assert!(reason.contains("NotValidForName"), "{}", reason),
+            other => panic!("expected a TLS error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Cursor, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::auth::{Challenge, Credentials};
+use super::pool::{self, Pool};
+use super::proxy::{Proxy, ProxyConfig, ProxyTransport};
+use super::tls::{self, TlsStream};
+use super::{RequestError, Url};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub trait Stream: Read + Write + Send {
+    // A second handle on the underlying socket, so another thread can shut
+    // it down while this one is blocked reading
+    fn socket(&self) -> Option<TcpStream> {
+        return None;
+    }
+}
+
+impl Stream for TcpStream {
+    fn socket(&self) -> Option<TcpStream> {
+        return self.try_clone().ok();
+    }
+}
+
+impl Stream for TlsStream {
+    fn socket(&self) -> Option<TcpStream> {
+        return self.sock.socket();
+    }
+}
+
+impl Stream for Box<dyn Stream> {
+    fn socket(&self) -> Option<TcpStream> {
+        return (**self).socket();
+    }
+}
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    sockets: Mutex<Vec<TcpStream>>,
+}
+
+// Stops the requests it is given to. Cancelling shuts down every socket
+// those requests are using, so reads blocked on a slow server return
+// at once instead of waiting out the timeout.
+#[derive(Clone, Default)]
+pub struct Cancel {
+    state: Arc<CancelState>,
+}
+
+impl Cancel {
+    pub fn new() -> Cancel {
+        return Cancel::default();
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        for socket in self.state.sockets.lock().unwrap().drain(..) {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.state.cancelled.load(Ordering::SeqCst);
+    }
+
+    // Registers a stream about to be used, failing if already cancelled
+    pub fn watch(&self, stream: &dyn Stream) -> Result<(), RequestError> {
+        let mut sockets = self.state.sockets.lock().unwrap();
+        if self.is_cancelled() {
+            return Err(RequestError::Cancelled);
+        }
+        sockets.extend(stream.socket());
+        return Ok(());
+    }
+}
+
+// Opens the byte stream a request is written to and its response read from.
+// `secure` asks for an encrypted connection; transports that cannot provide
+// one must fail rather than silently fall back to plain text.
+pub trait Transport {
+    fn connect(&mut self, host: &str, port: u16, secure: bool) -> Result<Box<dyn Stream>, RequestError>;
+
+    // Where persistent connections are kept between requests. Without a
+    // pool every request gets its own connection and asks for it to close.
+    fn pool(&self) -> Option<Arc<Pool>> {
+        return None;
+    }
+
+    // The http proxy a plain request to `url` is forwarded through, if any.
+    // Such a request is written with the absolute url as its target, on a
+    // connection to the proxy rather than the origin.
+    fn forward_proxy(&self, _url: &Url) -> Option<Proxy> {
+        return None;
+    }
+
+    // Has later connections through `proxy` answer its challenge with
+    // these credentials
+    fn authenticate_proxy(&mut self, _proxy: &Proxy, _credentials: Credentials, _challenge: Challenge) {}
+
+    // The host:port of the proxy a connection to host:port goes through, if
+    // any, so pooled connections are only reused along the same route
+    fn proxy_authority(&self, _host: &str, _port: u16, _secure: bool) -> Option<String> {
+        return None;
+    }
+
+    // A plain connection for protocols other than http, such as gopher and
+    // gemini, which a proxy has to tunnel rather than forward
+    fn connect_tunnel(&mut self, host: &str, port: u16) -> Result<Box<dyn Stream>, RequestError> {
+        return self.connect(host, port, false);
+    }
+
+    // A TLS connection over a tunnel, for gemini, whose servers are trusted
+    // on first use rather than through the web's certificate authorities.
+    // Returns the certificate the server presented so the caller can pin it.
+    fn connect_pinned(
+        &mut self,
+        host: &str,
+        port: u16,
+        cancel: Option<&Cancel>,
+    ) -> Result<(Box<dyn Stream>, Vec<u8>), RequestError> {
+        let socket = self.connect_tunnel(host, port)?;
+        if let Some(cancel) = cancel {
+            cancel.watch(&socket)?;
+        }
+        let stream = tls::connect_unverified(host, socket)?;
+        let certificate = match stream.conn.peer_certificates().and_then(|certificates| certificates.first()) {
+            Some(certificate) => certificate.to_vec(),
+            None => { return Err(RequestError::Tls(format!("{} presented no certificate", host))); }
+        };
+        return Ok((Box::new(stream), certificate));
+    }
+}
+
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn connect(&mut self, host: &str, port: u16, secure: bool) -> Result<Box<dyn Stream>, RequestError> {
+        if secure {
+            return Err(RequestError::Tls(format!("no TLS available for {}:{}", host, port)));
+        }
+        let addrs: Vec<SocketAddr> = (host, port)
+            .to_socket_addrs()
+            .map_err(|_| RequestError::Dns(host.to_string()))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(RequestError::Dns(host.to_string()));
+        }
+        let mut result = Err(io::Error::new(io::ErrorKind::NotFound, "no addresses"));
+        for addr in &addrs {
+            result = TcpStream::connect_timeout(addr, CONNECT_TIMEOUT);
+            if result.is_ok() {
+                break;
+            }
+        }
+        let socket = result.map_err(|e| RequestError::Connect(format!("{}:{}", host, port), e))?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        socket.set_write_timeout(Some(READ_TIMEOUT))?;
+        println!("Opened Socket");
+        return Ok(Box::new(socket));
+    }
+
+    fn pool(&self) -> Option<Arc<Pool>> {
+        return Some(pool::shared());
+    }
+}
+
+// Layers TLS over the plain connections of another transport
+pub struct TlsTransport<T: Transport> {
+    inner: T,
+}
+
+impl<T: Transport> TlsTransport<T> {
+    pub fn new(inner: T) -> TlsTransport<T> {
+        return TlsTransport { inner };
+    }
+}
+
+impl<T: Transport> Transport for TlsTransport<T> {
+    fn connect(&mut self, host: &str, port: u16, secure: bool) -> Result<Box<dyn Stream>, RequestError> {
+        let socket = self.inner.connect(host, port, false)?;
+        if !secure {
+            return Ok(socket);
+        }
+        return Ok(Box::new(tls::connect(host, socket)?));
+    }
+
+    fn pool(&self) -> Option<Arc<Pool>> {
+        return self.inner.pool();
+    }
+
+    fn forward_proxy(&self, url: &Url) -> Option<Proxy> {
+        return self.inner.forward_proxy(url);
+    }
+
+    fn authenticate_proxy(&mut self, proxy: &Proxy, credentials: Credentials, challenge: Challenge) {
+        self.inner.authenticate_proxy(proxy, credentials, challenge);
+    }
+
+    fn proxy_authority(&self, host: &str, port: u16, secure: bool) -> Option<String> {
+        return self.inner.proxy_authority(host, port, secure);
+    }
+
+    fn connect_tunnel(&mut self, host: &str, port: u16) -> Result<Box<dyn Stream>, RequestError> {
+        return self.inner.connect_tunnel(host, port);
+    }
+}
+
+pub fn default_transport() -> ProxyTransport<TlsTransport<TcpTransport>> {
+    return ProxyTransport::new(TlsTransport::new(TcpTransport), ProxyConfig::load());
+}
+
+struct MockStream {
+    response: Cursor<Vec<u8>>,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Stream for MockStream {}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.response.read(buf);
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockConnection {
+    pub host: String,
+    pub port: u16,
+    pub secure: bool,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl MockConnection {
+    // Everything the client wrote on this connection
+    pub fn sent(&self) -> Vec<u8> {
+        return self.written.lock().unwrap().clone();
+    }
+}
+
+// Replays canned responses without touching the network. Each connection to
+// a host and port takes the next response queued for it, in order, and
+// connecting with nothing queued is refused like a closed port would be.
+#[derive(Default)]
+pub struct MockTransport {
+    responses: HashMap<(String, u16), VecDeque<Vec<u8>>>,
+    connections: Vec<MockConnection>,
+    pool: Option<Arc<Pool>>,
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        return MockTransport::default();
+    }
+
+    pub fn respond(&mut self, host: &str, port: u16, response: &[u8]) -> &mut MockTransport {
+        self.responses
+            .entry((host.to_string(), port))
+            .or_default()
+            .push_back(response.to_vec());
+        return self;
+    }
+
+    // Pools connections like the network transports do, so a single canned
+    // response can hold several responses served on one connection
+    pub fn keep_alive(&mut self, max_per_origin: usize) -> &mut MockTransport {
+        self.pool = Some(Arc::new(Pool::new(max_per_origin)));
+        return self;
+    }
+
+    pub fn connections(&self) -> &[MockConnection] {
+        return &self.connections;
+    }
+}
+
+impl Transport for MockTransport {
+    fn connect(&mut self, host: &str, port: u16, secure: bool) -> Result<Box<dyn Stream>, RequestError> {
+        let response = self
+            .responses
+            .get_mut(&(host.to_string(), port))
+            .and_then(|queue| queue.pop_front())
+            .ok_or_else(|| {
+                RequestError::Connect(
+                    format!("{}:{}", host, port),
+                    io::Error::new(io::ErrorKind::ConnectionRefused, "no scripted response"),
+                )
+            })?;
+        let written = Arc::new(Mutex::new(Vec::new()));
+        self.connections.push(MockConnection {
+            host: host.to_string(),
+            port,
+            secure,
+            written: Arc::clone(&written),
+        });
+        return Ok(Box::new(MockStream { response: Cursor::new(response), written }));
+    }
+
+    fn pool(&self) -> Option<Arc<Pool>> {
+        return self.pool.clone();
+    }
+
+    // Answers in plain text as though the handshake had been made, with a
+    // certificate that is the same for every connection to a host and port
+    fn connect_pinned(
+        &mut self,
+        host: &str,
+        port: u16,
+        _cancel: Option<&Cancel>,
+    ) -> Result<(Box<dyn Stream>, Vec<u8>), RequestError> {
+        let stream = self.connect(host, port, true)?;
+        return Ok((stream, format!("certificate of {}:{}", host, port).into_bytes()));
+    }
+}
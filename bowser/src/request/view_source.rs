@@ -0,0 +1,187 @@
+use super::transport::Transport;
+use super::{HeaderMap, Request, RequestError, Response, Stores, Url};
+use crate::html::escape;
+
+const LINE_NUMBER_COLOR: &str = "#999999";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Text,
+    Tag,
+    AttrName,
+    AttrValue,
+    Comment,
+    Doctype,
+}
+
+fn color(kind: Kind) -> Option<&'static str> {
+    match kind {
+        Kind::Text => None,
+        Kind::Tag => Some("#881280"),
+        Kind::AttrName => Some("#994500"),
+        Kind::AttrValue => Some("#1a1aa6"),
+        Kind::Comment => Some("#236e25"),
+        Kind::Doctype => Some("#808080"),
+    }
+}
+
+struct Highlighter {
+    chars: Vec<char>,
+    i: usize,
+    spans: Vec<(Kind, String)>,
+}
+
+impl Highlighter {
+    fn new(source: &str) -> Highlighter {
+        return Highlighter { chars: source.chars().collect(), i: 0, spans: Vec::new() };
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        return prefix.chars().enumerate().all(|(j, c)| self.chars.get(self.i + j) == Some(&c));
+    }
+
+    fn push(&mut self, kind: Kind, c: char) {
+        match self.spans.last_mut() {
+            Some((last, text)) if *last == kind => text.push(c),
+            _ => self.spans.push((kind, c.to_string())),
+        }
+    }
+
+    // consumes characters as `kind` until `end` has been consumed or input runs out
+    fn until(&mut self, kind: Kind, end: &str) {
+        while self.i < self.chars.len() {
+            if self.starts_with(end) {
+                for c in end.chars() {
+                    self.push(kind, c);
+                }
+                self.i += end.chars().count();
+                return;
+            }
+            self.push(kind, self.chars[self.i]);
+            self.i += 1;
+        }
+    }
+
+    fn take_while(&mut self, kind: Kind, keep: fn(char) -> bool) {
+        while self.i < self.chars.len() && keep(self.chars[self.i]) {
+            self.push(kind, self.chars[self.i]);
+            self.i += 1;
+        }
+    }
+
+    fn tag(&mut self) {
+        self.push(Kind::Tag, '<');
+        self.i += 1;
+        self.take_while(Kind::Tag, |c| !c.is_whitespace() && c != '>');
+        while self.i < self.chars.len() {
+            let c = self.chars[self.i];
+            if c == '>' {
+                self.push(Kind::Tag, c);
+                self.i += 1;
+                return;
+            } else if c == '/' {
+                self.push(Kind::Tag, c);
+                self.i += 1;
+            } else if c.is_whitespace() {
+                self.take_while(Kind::Text, |c| c.is_whitespace());
+            } else if c == '=' {
+                self.push(Kind::Text, c);
+                self.i += 1;
+                self.take_while(Kind::Text, |c| c.is_whitespace());
+                self.value();
+            } else {
+                self.take_while(Kind::AttrName, |c| !c.is_whitespace() && c != '=' && c != '>');
+            }
+        }
+    }
+
+    fn value(&mut self) {
+        match self.chars.get(self.i) {
+            Some('"') => {
+                self.push(Kind::AttrValue, '"');
+                self.i += 1;
+                self.until(Kind::AttrValue, "\"");
+            }
+            Some('\'') => {
+                self.push(Kind::AttrValue, '\'');
+                self.i += 1;
+                self.until(Kind::AttrValue, "'");
+            }
+            _ => self.take_while(Kind::AttrValue, |c| !c.is_whitespace() && c != '>'),
+        }
+    }
+
+    fn run(mut self) -> Vec<(Kind, String)> {
+        while self.i < self.chars.len() {
+            if self.starts_with("<!--") {
+                self.until(Kind::Comment, "-->");
+            } else if self.starts_with("<!") || self.starts_with("<?") {
+                self.until(Kind::Doctype, ">");
+            } else if self.starts_with("<") {
+                self.tag();
+            } else {
+                self.push(Kind::Text, self.chars[self.i]);
+                self.i += 1;
+            }
+        }
+        return self.spans;
+    }
+}
+
+fn line_number(html: &mut String, line: usize) {
+    html.push_str(&format!("<font color=\"{}\">{:>5}  </font>", LINE_NUMBER_COLOR, line));
+}
+
+fn render(source: &str) -> String {
+    let mut html = String::from("<html><body><pre>");
+    let mut line = 1;
+    line_number(&mut html, line);
+    for (kind, text) in Highlighter::new(source).run() {
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                line += 1;
+                html.push('\n');
+                line_number(&mut html, line);
+            }
+            if part.is_empty() {
+                continue;
+            }
+            match color(kind) {
+                Some(color) => {
+                    html.push_str(&format!("<font color=\"{}\">{}</font>", color, escape(part)));
+                }
+                None => html.push_str(&escape(part)),
+            }
+        }
+    }
+    html.push_str("</pre></body></html>");
+    return html;
+}
+
+// Fetches the page inside a view-source: url over the caller's transport and
+// stores, always into memory since the source is what gets shown
+pub fn view_source<T: Transport>(url: &Url, transport: &mut T, stores: Stores) -> Result<Response, RequestError> {
+    let inner = Url::parse(&url.without_scheme())?;
+    if inner.scheme == "view-source" {
+        return Err(RequestError::UnsupportedScheme(inner.scheme));
+    }
+    let response = Request::get(&inner).send_with(transport, Stores { sink: None, ..stores })?;
+    // the credentials were used up by the request, so the page's url is shown without them
+    let final_url = Url::parse(&format!("view-source:{}", inner.without_userinfo()))?;
+    let source = response.text().replace("\r\n", "\n");
+
+    let source = source.strip_suffix('\n').unwrap_or(&source);
+    let body = render(source).into_bytes();
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", "text/html; charset=utf-8");
+    return Ok(Response {
+        status: response.status,
+        reason: response.reason,
+        headers,
+        decoded_length: body.len(),
+        body,
+        final_url,
+        streamed: false,
+    });
+}